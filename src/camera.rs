@@ -1,6 +1,9 @@
+use crate::environment::{Environment, Fog, Gradient};
 use crate::random::RandomGenerator;
-use crate::util::{linear_to_gamma, Ray, Vec3};
+use crate::util::{linear_to_gamma, power_heuristic, xyz_to_linear_srgb, Ray, RayDifferentials, Vec3};
 use crate::hittable::{HitRecord, Hittable, HittableList, Interval};
+use crate::light::LightList;
+use crate::spectrum::{wavelength_weight, Wavelengths};
 use image::ImageBuffer;
 use Vec3 as Point;
 use Vec3 as Color;
@@ -35,6 +38,13 @@ pub struct Camera {
     pub defocus_disk_u: Vec3,
     pub defocus_disk_v: Vec3,
 
+    // Light arriving from rays that escape the scene
+    pub environment: Box<dyn Environment>,
+
+    pub fog: Option<Fog>, // Haze that camera rays fade into with distance
+
+    pub spectral: bool, // Trace each sample at a few wavelengths and develop the film from CIE XYZ
+
 }
 
 impl Camera {
@@ -44,8 +54,8 @@ impl Camera {
         let max_depth: u32 = 10; // Maximum depth of recursion for ray tracing
         let vup = Vec3::new(0.0, 1.0, 0.0);
 
-        let defocus_angle = 3.0;
-        let focus_dist = 10.0;
+        let defocus_angle: f32 = 3.0;
+        let focus_dist: f32 = 10.0;
 
         // Image Constants
         let aspect_ratio: f32 = 16.0 / 9.0; // Ideal aspect ratio
@@ -78,13 +88,15 @@ impl Camera {
 
         // Location of upper left pixel
         let viewport_top_left = camera_center - (w * focus_dist) - (viewport_u / 2.0) - (viewport_v / 2.0);
-        let first_pixel_loc = viewport_top_left + (( pixel_delta_u / 2.0 + pixel_delta_v / 2.0) * 0.5 as f32);
+        let first_pixel_loc = viewport_top_left + (( pixel_delta_u / 2.0 + pixel_delta_v / 2.0) * 0.5);
 
-        let defocus_rad = focus_dist as f32 * (defocus_angle as f32 / 2.0).to_radians().tan();
+        let defocus_rad = focus_dist * (defocus_angle / 2.0).to_radians().tan();
         let defocus_disk_u = u * defocus_rad;
         let defocus_disk_v = v * defocus_rad;
         // let rand = Arc::new(UnsafeCell::new(RandomGenerator::new()));
 
+        let environment = Gradient::new(Color::new(1.0, 1.0, 1.0), Color::new(0.5 - 0.1, 0.7 - 0.1, 1.0));
+
         Camera {
             max_depth,
             img_width,
@@ -97,12 +109,15 @@ impl Camera {
             first_pixel_loc,
             defocus_angle,
             defocus_disk_u,
-            defocus_disk_v
+            defocus_disk_v,
+            environment,
+            fog: None,
+            spectral: false,
         }
     }
 
 
-    pub fn render(camera: Arc<Camera>, world: Arc<HittableList>, mut img_buf: ImageBuffer<image::Rgb<u8>, Vec<u8>>, img_path: String) {
+    pub fn render(camera: Arc<Camera>, world: Arc<HittableList>, lights: Arc<LightList>, mut img_buf: ImageBuffer<image::Rgb<u8>, Vec<u8>>, img_path: String) {
         
         println!("\nRunning Parallel Raytrace... \n");
         let now = SystemTime::now();
@@ -122,6 +137,7 @@ impl Camera {
 
             let camera = Arc::clone(&camera);
             let world = Arc::clone(&world);
+            let lights = Arc::clone(&lights);
             let tx = tx.clone();
 
             let handle = thread::spawn(move || {
@@ -142,12 +158,21 @@ impl Camera {
         
                         for _sample in 0..camera.samples_per_pixel {
                             let r = camera.get_ray(x as f32, y as f32, &mut rand);
-                            pixel_color = pixel_color + camera.ray_color(&r, &world, camera.max_depth, &mut rand);
+                            let radiance = camera.ray_color(&r, &world, &lights, camera.max_depth, None, &mut rand);
+                            pixel_color = pixel_color + match r.wavelengths {
+                                Some(wavelengths) => wavelengths.to_xyz(&radiance),
+                                None => radiance,
+                            };
                         }
-    
+
+                        let mut color = pixel_color * camera.pixel_sample_scale;
+                        if camera.spectral {
+                            color = xyz_to_linear_srgb(color.x, color.y, color.z);
+                        }
+
                         section.push(PixelData {
                             loc: (x, y),
-                            color: pixel_color * camera.pixel_sample_scale
+                            color
                         });
 
                     }
@@ -182,13 +207,13 @@ impl Camera {
 
         }
     
-        img_buf.save(&img_path).expect(("Unable to save image to ".to_owned() + &img_path).as_str());
+        img_buf.save(&img_path).unwrap_or_else(|_| panic!("Unable to save image to {}", &img_path));
         println!("\nImage saved to {}", &img_path);
 
         let after = SystemTime::now();
         let duration = after.duration_since(now).expect("Clock went backwards??");
 
-        println!("\nRender Stats: \n - Total render time: {} sec \n - Total Pixels Calculated: {} \n - Average px/ms: {} \n", &duration.as_secs(), camera.img_width * camera.img_height, (camera.img_width * camera.img_height) as u128 / &duration.as_millis());
+        println!("\nRender Stats: \n - Total render time: {} sec \n - Total Pixels Calculated: {} \n - Average px/ms: {} \n", &duration.as_secs(), camera.img_width * camera.img_height, (camera.img_width * camera.img_height) as u128 / duration.as_millis());
         
     }
 
@@ -202,7 +227,20 @@ impl Camera {
         let origin = if self.defocus_angle <= 0.0 { self.camera_center } else {self.defocus_disk_sample(rand)};
         let direction = pixel_sample - origin;
 
-        Ray::new(origin, direction)
+        // Each sample only has to cover its share of the pixel, so shrink the footprint as samples go up
+        let scale = (1.0 / (self.samples_per_pixel as f32).sqrt()).max(0.125);
+        let differentials = RayDifferentials {
+            rx_origin: origin,
+            rx_direction: direction + self.pixel_delta_u * scale,
+            ry_origin: origin,
+            ry_direction: direction + self.pixel_delta_v * scale,
+        };
+
+        let mut ray = Ray::with_differentials(origin, direction, Some(differentials));
+        if self.spectral {
+            ray.wavelengths = Some(Wavelengths::sample(rand.random_float()));
+        }
+        ray
     }
 
     fn defocus_disk_sample(&self, rand: &mut RandomGenerator) -> Vec3 {
@@ -210,28 +248,127 @@ impl Camera {
         self.camera_center + (self.defocus_disk_u * p.x) + (self.defocus_disk_v * p.y)
     }
 
-    fn ray_color(&self, ray: &Ray, world: &HittableList, depth: u32, rand: &mut RandomGenerator) -> Color {
+    // `scatter_pdf` is the pdf the previous bounce picked this ray with, or None if it was specular
+    // or came from the camera. It is used to weight against light sampling of the environment.
+    fn ray_color(&self, ray: &Ray, world: &HittableList, lights: &LightList, depth: u32, scatter_pdf: Option<f32>, rand: &mut RandomGenerator) -> Color {
 
-        if depth <= 0 {
+        if depth == 0 {
             return Color::new(0.0, 0.0, 0.0); // No more light is collected if at max depth
         }
 
         let rec = &mut HitRecord::new();
-        if world.hit(ray, Interval::new(0.001, f32::MAX), rec) {
-            let res = rec.material.scatter(ray, rec, rand);
-            if res.is_some() {
-                let (scattered, attenuation) = res.unwrap();
-                return &self.ray_color(&scattered, world, depth - 1, rand) * &attenuation;
+        if world.hit(ray, Interval::new(0.001, f32::MAX), rec, rand) {
+            rec.compute_differentials(ray);
+
+            let mut color = Camera::illuminant(ray, &rec.material.emitted(ray, rec))
+                + self.sample_environment(ray, world, rec, rand)
+                + Camera::sample_lights(ray, world, lights, rec, rand);
+
+            if let Some((mut scattered, attenuation)) = rec.material.scatter(ray, rec, rand) {
+                let mut attenuation = Camera::scattering(ray, rec, &attenuation);
+
+                // A dispersive surface split the path into a single wavelength. In spectral mode the hero
+                // wavelength carries on alone, otherwise the path turns into that wavelength's RGB.
+                if let (None, Some(lambda)) = (ray.wavelength, scattered.wavelength) {
+                    let split = if ray.wavelengths.is_some() { Color::new(3.0, 0.0, 0.0) } else { wavelength_weight(lambda) };
+                    attenuation = &attenuation * &split;
+                }
+                scattered.wavelength = scattered.wavelength.or(ray.wavelength);
+                scattered.wavelengths = ray.wavelengths;
+                scattered.media = scattered.media.or(ray.media);
+
+                let pdf = match scattered.specular {
+                    true => None,
+                    false => rec.material.eval(ray, rec, scattered.direction()).map(|(_, pdf)| pdf),
+                };
+                color = color + &self.ray_color(&scattered, world, lights, depth - 1, pdf, rand) * &attenuation;
+            }
+
+            // Everything leaving the hit is absorbed on its way back along the ray
+            color = &color * &Camera::absorption(ray, rec.t * ray.direction().length());
+
+            if let Some(fog) = self.fog && depth == self.max_depth {
+                let transmittance = fog.transmittance(rec.t * ray.direction().length());
+                color = color * transmittance + Camera::illuminant(ray, &fog.color) * (1.0 - transmittance);
             }
-            return Color::new(0.0, 0.0, 0.0); // No light is collected if no scatter occurs
+            return color; // No light is collected from the surface if no scatter occurs
         }
-    
+
         // Background color
-        let unit_vec = Vec3::unit_vector(ray.direction());
-        let a = 0.8 * (unit_vec.y + 1.0);
-        Color::new(1.0, 1.0, 1.0) * (1.0 - a) + Color::new(0.5 - 0.1, 0.7 - 0.1, 1.0) * a
+        let radiance = &Camera::illuminant(ray, &self.environment.radiance(ray.direction())) * &Camera::absorption(ray, f32::INFINITY);
+        match scatter_pdf {
+            Some(pdf) => radiance * power_heuristic(pdf, self.environment.pdf(ray.direction())),
+            None => radiance,
+        }
+    }
+
+    // Direct lighting from the environment, by casting a shadow ray towards an importance sampled direction
+    fn sample_environment(&self, ray: &Ray, world: &HittableList, rec: &HitRecord, rand: &mut RandomGenerator) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+
+        let Some((direction, light_pdf)) = self.environment.sample(rand) else { return black };
+        let Some((bsdf_cos, scatter_pdf)) = rec.material.eval(ray, rec, &direction) else { return black };
+
+        let shadow_ray = Ray::new(rec.point, direction);
+        let transmittance = world.transmittance(&shadow_ray, Interval::new(0.001, f32::MAX), rand);
+        if transmittance <= 0.0 {
+            return black;
+        }
+
+        let weight = power_heuristic(light_pdf, scatter_pdf);
+        let radiance = &Camera::illuminant(ray, &self.environment.radiance(&direction)) * &Camera::shadow_absorption(ray, rec, &direction, f32::INFINITY);
+        &Camera::scattering(ray, rec, &bsdf_cos) * &radiance * (transmittance * weight / light_pdf)
     }
     
+    // Direct lighting from the analytic lights. They can't be hit by scattered rays, so no weighting is needed.
+    fn sample_lights(ray: &Ray, world: &HittableList, lights: &LightList, rec: &HitRecord, rand: &mut RandomGenerator) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+
+        for light in lights.iter() {
+            let Some(sample) = light.sample(&rec.point, ray.wavelengths.as_ref(), rand) else { continue };
+            let Some((bsdf_cos, _)) = rec.material.eval(ray, rec, &sample.direction) else { continue };
+
+            let shadow_ray = Ray::new(rec.point, sample.direction);
+            let transmittance = world.transmittance(&shadow_ray, Interval::new(0.001, sample.distance * 0.999), rand);
+            if transmittance <= 0.0 {
+                continue;
+            }
+
+            let radiance = &sample.radiance * &Camera::shadow_absorption(ray, rec, &sample.direction, sample.distance);
+            color = color + &Camera::scattering(ray, rec, &bsdf_cos) * &radiance * transmittance;
+        }
+
+        color
+    }
+
+    // Beer-Lambert absorption over a stretch of the ray through the dielectric medium it is in
+    fn absorption(ray: &Ray, distance: f32) -> Color {
+        match ray.media.as_ref().and_then(|media| media.current()) {
+            Some(medium) => Camera::reflectance(ray, &medium.transmittance(distance)),
+            None => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    // Shadow rays leaving on the side the ray arrived from travel through its medium. Those going through the
+    // surface head into whatever is behind it, and any dielectric boundary on the way blocks them anyway.
+    fn shadow_absorption(ray: &Ray, rec: &HitRecord, direction: &Vec3, distance: f32) -> Color {
+        if Vec3::dot(direction, &rec.normal) > 0.0 { Camera::absorption(ray, distance) } else { Color::new(1.0, 1.0, 1.0) }
+    }
+
+    // Materials and environments work in RGB, which spectral paths up-sample to their wavelengths
+    fn reflectance(ray: &Ray, rgb: &Color) -> Color {
+        ray.wavelengths.map_or(*rgb, |wavelengths| wavelengths.upsample(rgb))
+    }
+
+    // What a material scattered, unless it already answered at the path's wavelengths
+    fn scattering(ray: &Ray, rec: &HitRecord, value: &Color) -> Color {
+        if rec.material.spectral(ray) { *value } else { Camera::reflectance(ray, value) }
+    }
+
+    fn illuminant(ray: &Ray, rgb: &Color) -> Color {
+        ray.wavelengths.map_or(*rgb, |wavelengths| wavelengths.upsample_illuminant(rgb))
+    }
+
     fn write_color(pixel: &mut image::Rgb<u8>, color: Color) {
         let mut r = color.x;
         let mut g = color.y;
@@ -242,9 +379,9 @@ impl Camera {
         b = linear_to_gamma(b);
 
         static INTENSITY: Interval = Interval::new(0.0, 0.99999);
-        let rbyte = (256.0 * INTENSITY.clamp(r as f32)) as u8;
-        let gbyte = (256.0 * INTENSITY.clamp(g as f32)) as u8;
-        let bbyte = (256.0 * INTENSITY.clamp(b as f32)) as u8;
+        let rbyte = (256.0 * INTENSITY.clamp(r)) as u8;
+        let gbyte = (256.0 * INTENSITY.clamp(g)) as u8;
+        let bbyte = (256.0 * INTENSITY.clamp(b)) as u8;
     
         *pixel = image::Rgb([rbyte, gbyte, bbyte]);
         // image::Rgb([rbyte, gbyte, bbyte])
@@ -0,0 +1,520 @@
+use std::f32::consts::PI;
+
+use crate::random::RandomGenerator;
+use crate::util::{xyz_to_linear_srgb, Vec3};
+
+use Vec3 as Color;
+
+// Light arriving from infinitely far away, looked up by rays that escape the scene
+pub trait Environment: Send + Sync {
+    fn radiance(&self, direction: &Vec3) -> Color;
+
+    // Picks a direction worth sending a shadow ray towards, along with its solid angle pdf.
+    // Environments that can't be importance sampled return None and are only found by escaping rays.
+    fn sample(&self, _rand: &mut RandomGenerator) -> Option<(Vec3, f32)> {
+        None
+    }
+
+    // Solid angle pdf that `sample` would have picked `direction` with
+    fn pdf(&self, _direction: &Vec3) -> f32 {
+        0.0
+    }
+}
+
+
+// ----------------------------------------------
+// Gradient background --------------------------
+// ----------------------------------------------
+pub struct Gradient {
+    bottom: Color,
+    top: Color,
+}
+
+impl Gradient {
+    pub fn new(bottom: Color, top: Color) -> Box<Self> {
+        Box::new(Gradient { bottom, top })
+    }
+}
+
+impl Environment for Gradient {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let unit_vec = Vec3::unit_vector(direction);
+        let a = 0.8 * (unit_vec.y + 1.0);
+        self.bottom * (1.0 - a) + self.top * a
+    }
+}
+
+
+// ----------------------------------------------
+// Equirectangular environment map --------------
+// ----------------------------------------------
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+
+    intensity: f32,
+    rotation: f32, // Rotation around the y-axis in radians
+
+    // Luminance weighted distribution over the image used for importance sampling
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    // Loads an equirectangular .hdr or .exr image. Rotation is in degrees around the y-axis.
+    pub fn load(path: &str, intensity: f32, rotation: f32) -> Result<Box<Self>, image::ImageError> {
+        let img = image::open(path)?.into_rgb32f();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+
+        let pixels: Vec<Color> = img.pixels().map(|px| Color::new(px[0], px[1], px[2])).collect();
+
+        // Rows near the poles cover less solid angle, so weight them down by sin(theta)
+        let mut weights = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            for x in 0..width {
+                weights.push(luminance(&pixels[y * width + x]) * sin_theta);
+            }
+        }
+
+        let distribution = Distribution2D::new(&weights, width, height);
+
+        Ok(Box::new(EnvironmentMap {
+            width,
+            height,
+            pixels,
+            intensity,
+            rotation: rotation.to_radians(),
+            distribution,
+        }))
+    }
+
+    // Maps a world direction to image coordinates in [0, 1), with v = 0 at the top row
+    fn direction_to_uv(&self, direction: &Vec3) -> (f32, f32) {
+        let d = rotate_y(&direction.unit_vector(), -self.rotation);
+        let phi = d.x.atan2(-d.z);
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        (0.5 + phi / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI;
+        let theta = v * PI;
+        let d = Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        rotate_y(&d, self.rotation)
+    }
+
+    fn lookup(&self, u: f32, v: f32) -> Color {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(direction);
+        self.lookup(u, v) * self.intensity
+    }
+
+    fn sample(&self, rand: &mut RandomGenerator) -> Option<(Vec3, f32)> {
+        let (u, v, map_pdf) = self.distribution.sample(rand.random_float(), rand.random_float());
+        if map_pdf <= 0.0 {
+            return None;
+        }
+
+        // Convert from a density over the image to a density over solid angle
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+
+        Some((self.uv_to_direction(u, v), map_pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f32 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+fn rotate_y(v: &Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(cos * v.x + sin * v.z, v.y, -sin * v.x + cos * v.z)
+}
+
+pub fn luminance(color: &Color) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+
+// ----------------------------------------------
+// Piecewise constant distributions -------------
+// ----------------------------------------------
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func: &[f32]) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f32;
+        }
+
+        let integral = cdf[n];
+        if integral > 0.0 {
+            for value in cdf.iter_mut() {
+                *value /= integral;
+            }
+        } else {
+            // Nothing to importance sample, fall back to uniform
+            for (i, value) in cdf.iter_mut().enumerate() {
+                *value = i as f32 / n as f32;
+            }
+        }
+
+        Distribution1D { func: func.to_vec(), cdf, integral }
+    }
+
+    // Returns a position in [0, 1), its pdf and the bucket it landed in
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.func.len();
+        let index = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 { (u - self.cdf[index]) / width } else { 0.0 };
+
+        ((index as f32 + offset) / n as f32, self.bucket_pdf(index), index)
+    }
+
+    fn bucket_pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 { self.func[index].max(0.0) / self.integral } else { 1.0 }
+    }
+}
+
+struct Distribution2D {
+    conditional: Vec<Distribution1D>, // One per row
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    fn new(weights: &[f32], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..height)
+            .map(|y| Distribution1D::new(&weights[y * width..(y + 1) * width]))
+            .collect();
+        let row_integrals: Vec<f32> = conditional.iter().map(|row| row.integral).collect();
+
+        Distribution2D { conditional, marginal: Distribution1D::new(&row_integrals) }
+    }
+
+    fn sample(&self, u0: f32, u1: f32) -> (f32, f32, f32) {
+        let (v, pdf_v, row) = self.marginal.sample(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample(u0);
+        (u, v, pdf_u * pdf_v)
+    }
+
+    fn pdf(&self, u: f32, v: f32) -> f32 {
+        let height = self.conditional.len();
+        let row = ((v * height as f32) as usize).min(height - 1);
+        let width = self.conditional[row].func.len();
+        let column = ((u * width as f32) as usize).min(width - 1);
+
+        self.marginal.bucket_pdf(row) * self.conditional[row].bucket_pdf(column)
+    }
+}
+
+
+// ----------------------------------------------
+// Preetham physical sky ------------------------
+// ----------------------------------------------
+const SUN_ANGULAR_RADIUS: f32 = 0.00465; // Radians, as seen from the earth
+const SUN_LUMINANCE: f32 = 1.6e6; // Same units as the sky model (kcd/m^2) before atmospheric extinction
+
+pub struct Sky {
+    sun_direction: Vec3,
+    sun_radiance: Color,
+    sun_sample_probability: f32,
+
+    // Perez distribution coefficients for luminance and the two chromaticity channels
+    perez_luminance: [f32; 5],
+    perez_x: [f32; 5],
+    perez_y: [f32; 5],
+
+    // Zenith values divided by the Perez function at the zenith, so radiance is a single multiply
+    zenith_luminance: f32,
+    zenith_x: f32,
+    zenith_y: f32,
+
+    intensity: f32,
+}
+
+impl Sky {
+    // Sun elevation and azimuth are in degrees. Azimuth is measured clockwise from north (-z) towards east (+x).
+    // Turbidity ranges from about 2 (very clear) to 10 (hazy).
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, intensity: f32) -> Box<Self> {
+        let elevation = elevation.to_radians();
+        let azimuth = azimuth.to_radians();
+        let t = turbidity;
+
+        let sun_direction = Vec3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        );
+
+        // The model is only valid with the sun above the horizon
+        let theta_s = (std::f32::consts::FRAC_PI_2 - elevation).min(std::f32::consts::FRAC_PI_2);
+
+        let perez_luminance = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_y = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+
+        let th = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+        let zenith_x = t * t * (0.00166 * th[0] - 0.00375 * th[1] + 0.00209 * th[2])
+            + t * (-0.02903 * th[0] + 0.06377 * th[1] - 0.03202 * th[2] + 0.00394 * th[3])
+            + (0.11693 * th[0] - 0.21196 * th[1] + 0.06052 * th[2] + 0.25886 * th[3]);
+        let zenith_y = t * t * (0.00275 * th[0] - 0.00610 * th[1] + 0.00317 * th[2])
+            + t * (-0.04214 * th[0] + 0.08970 * th[1] - 0.04153 * th[2] + 0.00516 * th[3])
+            + (0.15346 * th[0] - 0.26756 * th[1] + 0.06670 * th[2] + 0.26688 * th[3]);
+
+        let sun_radiance = if elevation > -SUN_ANGULAR_RADIUS {
+            sun_transmittance(theta_s, t) * SUN_LUMINANCE
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        };
+
+        Box::new(Sky {
+            sun_direction,
+            sun_radiance,
+            sun_sample_probability: if elevation > -SUN_ANGULAR_RADIUS { 0.5 } else { 0.0 },
+            zenith_luminance: zenith_luminance / perez(&perez_luminance, 0.0, theta_s),
+            zenith_x: zenith_x / perez(&perez_x, 0.0, theta_s),
+            zenith_y: zenith_y / perez(&perez_y, 0.0, theta_s),
+            perez_luminance,
+            perez_x,
+            perez_y,
+            intensity,
+        })
+    }
+
+    // Places the sun for a local solar time in hours, at a latitude in degrees on a day of the year (1-365)
+    pub fn at_time(hour: f32, latitude: f32, day_of_year: u32, turbidity: f32, intensity: f32) -> Box<Self> {
+        let latitude = latitude.to_radians();
+        let declination = (-23.44f32).to_radians() * (2.0 * PI * (day_of_year as f32 + 10.0) / 365.0).cos();
+        let hour_angle = (15.0 * (hour - 12.0)).to_radians();
+
+        let sin_elevation = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+
+        let cos_azimuth = (declination.sin() - sin_elevation * latitude.sin()) / (elevation.cos() * latitude.cos());
+        let mut azimuth = cos_azimuth.clamp(-1.0, 1.0).acos();
+        if hour_angle > 0.0 {
+            azimuth = 2.0 * PI - azimuth; // Afternoon sun is in the west
+        }
+
+        Sky::new(elevation.to_degrees(), azimuth.to_degrees(), turbidity, intensity)
+    }
+
+    fn sky_radiance(&self, direction: &Vec3) -> Color {
+        // Below the horizon, show a dim ground lit by the horizon sky
+        let cos_theta = direction.y.max(0.01);
+        let theta = cos_theta.acos();
+        let gamma = Vec3::dot(direction, &self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let luminance = self.zenith_luminance * perez(&self.perez_luminance, theta, gamma);
+        let x = self.zenith_x * perez(&self.perez_x, theta, gamma);
+        let y = self.zenith_y * perez(&self.perez_y, theta, gamma);
+
+        let color = xyy_to_linear_srgb(x, y, luminance);
+        if direction.y < 0.0 { color * 0.3 } else { color }
+    }
+
+    fn sun_cone_pdf() -> f32 {
+        1.0 / (2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos()))
+    }
+
+    fn in_sun_disk(&self, direction: &Vec3) -> bool {
+        Vec3::dot(direction, &self.sun_direction) >= SUN_ANGULAR_RADIUS.cos()
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let d = direction.unit_vector();
+        let mut color = self.sky_radiance(&d);
+
+        if self.in_sun_disk(&d) {
+            color = color + self.sun_radiance;
+        }
+
+        color * self.intensity
+    }
+
+    // Picks the sun disk as a directional light half of the time, and the whole sphere otherwise
+    fn sample(&self, rand: &mut RandomGenerator) -> Option<(Vec3, f32)> {
+        let direction = if rand.random_float() < self.sun_sample_probability {
+            rand.random_in_cone(&self.sun_direction, SUN_ANGULAR_RADIUS.cos())
+        } else {
+            rand.random_unit_vector_on_sphere()
+        };
+
+        Some((direction, self.pdf(&direction)))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f32 {
+        let d = direction.unit_vector();
+        let sun = if self.in_sun_disk(&d) { Sky::sun_cone_pdf() } else { 0.0 };
+        self.sun_sample_probability * sun + (1.0 - self.sun_sample_probability) / (4.0 * PI)
+    }
+}
+
+// Perez et al. all-weather sky luminance distribution
+fn perez(coefficients: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / theta.cos().max(0.01)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// Rayleigh and aerosol extinction of sunlight along the air mass for a sun at zenith angle theta_s
+fn sun_transmittance(theta_s: f32, turbidity: f32) -> Color {
+    let zenith_degrees = theta_s.to_degrees();
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - zenith_degrees).max(0.1).powf(-1.253));
+
+    let beta = 0.04608 * turbidity - 0.04586; // Angstrom turbidity coefficient
+    let channel = |wavelength_um: f32| {
+        let rayleigh = 0.008735 * wavelength_um.powf(-4.08);
+        let aerosol = beta * wavelength_um.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+
+    Color::new(channel(0.680), channel(0.550), channel(0.440))
+}
+
+fn xyy_to_linear_srgb(x: f32, y: f32, luminance: f32) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    xyz_to_linear_srgb(big_x, luminance, big_z)
+}
+
+
+// ----------------------------------------------
+// Atmospheric fog ------------------------------
+// ----------------------------------------------
+
+// Cheap haze over the whole scene: camera rays fade towards `color` with the distance to what they hit,
+// while the background stays as it is. Use a ConstantMedium where light should actually scatter.
+#[derive(Debug, Clone, Copy)]
+pub struct Fog {
+    pub color: Color,
+    pub density: f32, // Fraction of light lost per unit of distance
+}
+
+impl Fog {
+    pub fn new(color: Color, density: f32) -> Self {
+        Fog { color, density: density.max(0.0) }
+    }
+
+    pub fn transmittance(&self, distance: f32) -> f32 {
+        (-self.density * distance).exp()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stratified samples land in each bucket as often as the pdf says, spread over the bucket width
+    #[test]
+    fn distribution_1d_pdf_matches_sample_frequency() {
+        let func = [1.0, 3.0, 0.0, 4.0, 2.0];
+        let distribution = Distribution1D::new(&func);
+
+        let n = 100_000;
+        let mut counts = [0usize; 5];
+        for i in 0..n {
+            let (x, pdf, index) = distribution.sample((i as f32 + 0.5) / n as f32);
+            assert_eq!(index, ((x * func.len() as f32) as usize).min(func.len() - 1));
+            assert_eq!(pdf, distribution.bucket_pdf(index));
+            counts[index] += 1;
+        }
+
+        for (index, &count) in counts.iter().enumerate() {
+            let frequency = count as f32 / n as f32 * func.len() as f32;
+            assert!((frequency - distribution.bucket_pdf(index)).abs() < 1e-3, "bucket {index}: {frequency}");
+        }
+        assert_eq!(counts[2], 0);
+    }
+
+    #[test]
+    fn distribution_1d_falls_back_to_uniform() {
+        let distribution = Distribution1D::new(&[0.0, 0.0, 0.0, 0.0]);
+        let (x, pdf, index) = distribution.sample(0.6);
+        assert_eq!(index, 2);
+        assert!((x - 0.6).abs() < 1e-6);
+        assert_eq!(pdf, 1.0);
+    }
+
+    #[test]
+    fn distribution_2d_pdf_matches_sample_frequency() {
+        let (width, height) = (4, 3);
+        let weights = [1.0, 2.0, 0.0, 1.0, 5.0, 1.0, 1.0, 1.0, 0.0, 0.0, 3.0, 9.0];
+        let distribution = Distribution2D::new(&weights, width, height);
+
+        let n = 400;
+        let mut counts = [0usize; 12];
+        for i in 0..n {
+            for j in 0..n {
+                let (u, v, pdf) = distribution.sample((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                assert!((pdf - distribution.pdf(u, v)).abs() < 1e-4 * pdf.max(1.0));
+                let column = ((u * width as f32) as usize).min(width - 1);
+                let row = ((v * height as f32) as usize).min(height - 1);
+                counts[row * width + column] += 1;
+            }
+        }
+
+        let total: f32 = weights.iter().sum();
+        for (cell, &count) in counts.iter().enumerate() {
+            let frequency = count as f32 / (n * n) as f32 * (width * height) as f32;
+            let (u, v) = ((cell % width) as f32 + 0.5, (cell / width) as f32 + 0.5);
+            let pdf = distribution.pdf(u / width as f32, v / height as f32);
+            assert!((frequency - pdf).abs() < 1e-2, "cell {cell}: {frequency} against {pdf}");
+            assert!((pdf - weights[cell] * (width * height) as f32 / total).abs() < 1e-4);
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use core::f32;
 use std::sync::Arc;
 
-use crate::util::{Ray, Vec3};
+use crate::util::{Onb, Ray, RayDifferentials, Vec3};
 use crate::material::{Lambertian, Material};
+use crate::random::RandomGenerator;
+use crate::texture::{Texture, UvDerivatives};
 
 use Vec3 as Point;
 use Vec3 as Color;
@@ -11,8 +13,16 @@ use Vec3 as Color;
 #[derive(Debug)]
 pub struct HitRecord {
     pub point: Point,
-    pub normal: Vec3,
+    pub normal: Vec3, // Geometric normal, facing against the ray. Used to keep rays on the right side of the surface.
+    pub shading_normal: Vec3, // Normal used by materials, which normal and bump maps may perturb
     pub t: f32,
+    pub u: f32, // Surface coordinates of the hit point, used for texture lookups
+    pub v: f32,
+    pub dpdu: Vec3, // Partial derivatives of the surface position, spanning the tangent plane
+    pub dpdv: Vec3,
+    pub dpdx: Vec3, // How far the hit point moves one pixel over, when the ray has differentials
+    pub dpdy: Vec3,
+    pub uv_derivatives: UvDerivatives,
     pub front_face: bool,
     pub material: Box<dyn Material>,
 }
@@ -21,13 +31,135 @@ impl HitRecord {
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
         self.front_face = Vec3::dot(ray.direction(), outward_normal) < 0.0;
         self.normal = if self.front_face { *outward_normal } else { (*outward_normal) * -1.0 };
+        self.shading_normal = self.normal;
+    }
+
+    // Replaces the shading normal, bending it back towards the geometric normal where needed so a
+    // mirror reflection of the incoming ray still leaves the surface instead of going below the horizon
+    pub fn set_shading_normal(&mut self, ray_in: &Ray, shading_normal: &Vec3) {
+        let mut n = shading_normal.unit_vector();
+        if Vec3::dot(&n, &self.normal) < 0.0 {
+            n = n * -1.0; // Keep the shading normal on the same side as the geometric one
+        }
+
+        let d = ray_in.direction().unit_vector();
+        let leaves_surface = |n: &Vec3| Vec3::dot(&Vec3::reflect(&d, n), &self.normal) > 0.01;
+
+        if !leaves_surface(&n) {
+            // Bisect for the largest blend away from the geometric normal that still reflects above the surface
+            let (mut low, mut high) = (0.0, 1.0);
+            for _ in 0..8 {
+                let mid = 0.5 * (low + high);
+                let blended = (self.normal * (1.0 - mid) + n * mid).unit_vector();
+                if leaves_surface(&blended) { low = mid } else { high = mid }
+            }
+            n = (self.normal * (1.0 - low) + n * low).unit_vector();
+        }
+
+        self.shading_normal = n;
+    }
+
+    // Orthonormal tangent frame around the shading normal, with the tangent following dpdu
+    pub fn shading_frame(&self) -> Onb {
+        Onb::with_tangent(&self.shading_normal, &self.dpdu)
+    }
+
+    pub fn set_surface_coords(&mut self, u: f32, v: f32, dpdu: Vec3, dpdv: Vec3) {
+        self.u = u;
+        self.v = v;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self.uv_derivatives = UvDerivatives::default(); // Until `compute_differentials`, so lookups while intersecting are unfiltered
+    }
+
+    // Whether the material is there at the hit. Cut-out parts let a matching share of rays through,
+    // as if the surface had never been hit.
+    pub fn is_opaque(&self, rand: &mut RandomGenerator) -> bool {
+        let alpha = self.material.alpha(self);
+        alpha >= 1.0 || rand.random_float() < alpha
+    }
+
+    // Intersects the ray's differentials with the tangent plane to find the pixel footprint in uv space
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        self.dpdx = Vec3::new(0.0, 0.0, 0.0);
+        self.dpdy = Vec3::new(0.0, 0.0, 0.0);
+        self.uv_derivatives = UvDerivatives::default();
+
+        let Some(diff) = ray.differentials else { return };
+
+        let n = self.normal;
+        let d = Vec3::dot(&n, &self.point);
+        let denom_x = Vec3::dot(&n, &diff.rx_direction);
+        let denom_y = Vec3::dot(&n, &diff.ry_direction);
+        if denom_x.abs() < 1e-8 || denom_y.abs() < 1e-8 {
+            return; // Grazing angle, the footprint is unbounded
+        }
+
+        let tx = (d - Vec3::dot(&n, &diff.rx_origin)) / denom_x;
+        let ty = (d - Vec3::dot(&n, &diff.ry_origin)) / denom_y;
+        self.dpdx = diff.rx_origin + diff.rx_direction * tx - self.point;
+        self.dpdy = diff.ry_origin + diff.ry_direction * ty - self.point;
+
+        // Least squares solve of dpdx = dpdu * dudx + dpdv * dvdx, and the same for y
+        let a00 = Vec3::dot(&self.dpdu, &self.dpdu);
+        let a01 = Vec3::dot(&self.dpdu, &self.dpdv);
+        let a11 = Vec3::dot(&self.dpdv, &self.dpdv);
+        let det = a00 * a11 - a01 * a01;
+        if det.abs() < 1e-12 {
+            return;
+        }
+
+        let solve = |dp: &Vec3| {
+            let b0 = Vec3::dot(&self.dpdu, dp);
+            let b1 = Vec3::dot(&self.dpdv, dp);
+            ((a11 * b0 - a01 * b1) / det, (a00 * b1 - a01 * b0) / det)
+        };
+
+        let (dudx, dvdx) = solve(&self.dpdx);
+        let (dudy, dvdy) = solve(&self.dpdy);
+        self.uv_derivatives = UvDerivatives { dudx, dvdx, dudy, dvdy };
+    }
+
+    // Differentials for a perfect mirror bounce, treating the surface as locally flat
+    pub fn reflected_differentials(&self, ray_in: &Ray) -> Option<RayDifferentials> {
+        let diff = ray_in.differentials?;
+        Some(RayDifferentials {
+            rx_origin: self.point + self.dpdx,
+            rx_direction: Vec3::reflect(&diff.rx_direction.unit_vector(), &self.shading_normal),
+            ry_origin: self.point + self.dpdy,
+            ry_direction: Vec3::reflect(&diff.ry_direction.unit_vector(), &self.shading_normal),
+        })
+    }
+
+    // Differentials for a perfectly smooth refraction with the given ratio of refractive indices
+    pub fn refracted_differentials(&self, ray_in: &Ray, ni_over_nt: f32) -> Option<RayDifferentials> {
+        let diff = ray_in.differentials?;
+        Some(RayDifferentials {
+            rx_origin: self.point + self.dpdx,
+            rx_direction: Vec3::refract(&diff.rx_direction.unit_vector(), &self.shading_normal, ni_over_nt),
+            ry_origin: self.point + self.dpdy,
+            ry_direction: Vec3::refract(&diff.ry_direction.unit_vector(), &self.shading_normal, ni_over_nt),
+        })
+    }
+
+    // Looks up a texture at the hit, filtered over the pixel footprint when there is one
+    pub fn lookup(&self, texture: &Arc<dyn Texture>) -> Color {
+        texture.filtered_value(self.u, self.v, &self.point, &self.uv_derivatives)
     }
 
     pub fn new() -> Self {
         HitRecord {
             point: Point::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0),
+            shading_normal: Vec3::new(0.0, 0.0, 0.0),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            dpdx: Vec3::new(0.0, 0.0, 0.0),
+            dpdy: Vec3::new(0.0, 0.0, 0.0),
+            uv_derivatives: UvDerivatives::default(),
             front_face: false,
             material: Lambertian::new(Color::new(1.0, 1.0, 1.0)),
         }
@@ -37,7 +169,15 @@ impl HitRecord {
         HitRecord {
             point: self.point,
             normal: self.normal,
+            shading_normal: self.shading_normal,
             t: self.t,
+            u: self.u,
+            v: self.v,
+            dpdu: self.dpdu,
+            dpdv: self.dpdv,
+            dpdx: self.dpdx,
+            dpdy: self.dpdy,
+            uv_derivatives: self.uv_derivatives,
             front_face: self.front_face,
             material: self.material.clone(),
         }
@@ -45,7 +185,16 @@ impl HitRecord {
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord) -> bool;
+    // On a hit, primitives must fill in the whole record, including the surface
+    // parameterization (u, v, dpdu, dpdv) through `set_surface_coords`.
+    // Participating media hit at random distances, so they are handed a generator.
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool;
+
+    // Fraction of light getting through along the ray, for shadow rays. Surfaces block it completely,
+    // while media can estimate how much of it they let through.
+    fn transmittance(&self, r: &Ray, interval: Interval, rand: &mut RandomGenerator) -> f32 {
+        if self.hit(r, interval, &mut HitRecord::new(), rand) { 0.0 } else { 1.0 }
+    }
 }
 
 pub struct Sphere {
@@ -58,10 +207,33 @@ impl Sphere {
     pub fn new(center: Point, radius: f32, material: Box<dyn Material>) -> Arc<Self> {
         Arc::new(Sphere { center, radius: radius.max(0.0), material })
     }
+
+    // Maps a point on the unit sphere to (u, v), with u going around the y-axis from -x and v from -y to +y.
+    // Also returns the derivatives of the (unscaled) sphere surface with respect to u and v.
+    fn get_sphere_uv(&self, p: &Point) -> (f32, f32, Vec3, Vec3) {
+        let pi = f32::consts::PI;
+
+        let theta = (-p.y).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z).atan2(p.x) + pi;
+        let sin_theta = theta.sin();
+
+        // The parameterization collapses at the poles, so pick any tangent frame there
+        if sin_theta < 1e-4 {
+            let frame = Onb::new(p);
+            let dpdu = frame.u * (2.0 * pi * self.radius * 1e-4);
+            let dpdv = frame.v * (pi * self.radius);
+            return (phi / (2.0 * pi), theta / pi, dpdu, dpdv);
+        }
+
+        let dpdu = Vec3::new(p.z, 0.0, -p.x) * (2.0 * pi * self.radius);
+        let dpdv = Vec3::new(-p.x * p.y / sin_theta, sin_theta, -p.z * p.y / sin_theta) * (pi * self.radius);
+
+        (phi / (2.0 * pi), theta / pi, dpdu, dpdv)
+    }
 }
 
 impl Hittable for Sphere {
-    fn hit (&self, r: &Ray, interval: Interval, rec: &mut HitRecord) -> bool {
+    fn hit (&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
 
         let oc = self.center - *r.origin();
         let a = r.direction().length_squared();
@@ -73,26 +245,168 @@ impl Hittable for Sphere {
             return false;
         }
 
+        // Try the near root first, and the far one if it's out of range or cut out
         let sqrt = discriminant.sqrt();
-        let mut root = (h - sqrt) / a;
-
-        if !interval.surrounds(root) {
-            root = (h + sqrt) / a;
+        for root in [(h - sqrt) / a, (h + sqrt) / a] {
             if !interval.surrounds(root) {
-                return false;
+                continue;
             }
+
+            rec.t = root;
+            rec.point = r.at(root);
+            rec.normal = (rec.point - self.center) / self.radius;
+            rec.material = self.material.clone();
+
+            let outward_normal = (rec.point - self.center) / self.radius;
+            rec.set_face_normal(r, &outward_normal);
+            let (u, v, dpdu, dpdv) = self.get_sphere_uv(&outward_normal);
+            rec.set_surface_coords(u, v, dpdu, dpdv);
+
+            if rec.is_opaque(rand) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+
+// Flat parallelogram spanned by two edges from a corner, for walls, floors and boxes.
+// The outward normal follows the right hand rule from `u` to `v`, and (u, v) run from 0 to 1 along the edges.
+pub struct Quad {
+    corner: Point,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3, // Turns a point on the plane into its coordinates along the edges
+    normal: Vec3,
+    offset: f32, // Plane equation: dot(normal, p) = offset
+    material: Box<dyn Material>,
+}
+
+impl Quad {
+    // The edges `u` and `v` may be any two vectors that aren't parallel, like the sides of a parallelogram
+    pub fn new(corner: Point, u: Vec3, v: Vec3, material: Box<dyn Material>) -> Arc<Self> {
+        let n = Vec3::cross(&u, &v);
+        assert!(!n.near_zero(), "quad edges {u:?} and {v:?} span no area");
+        let normal = n.unit_vector();
+        Arc::new(Quad { corner, u, v, w: n / Vec3::dot(&n, &n), normal, offset: Vec3::dot(&normal, &corner), material })
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        let denominator = Vec3::dot(&self.normal, r.direction());
+        if denominator.abs() < 1e-8 {
+            return false; // Parallel to the plane
         }
 
-        rec.t = root;
-        rec.point = r.at(root);
-        rec.normal = (rec.point - self.center) / self.radius;
+        let t = (self.offset - Vec3::dot(&self.normal, r.origin())) / denominator;
+        if !interval.surrounds(t) {
+            return false;
+        }
+
+        let point = r.at(t);
+        let planar = point - self.corner;
+        let alpha = Vec3::dot(&self.w, &Vec3::cross(&planar, &self.v));
+        let beta = Vec3::dot(&self.w, &Vec3::cross(&self.u, &planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.point = point;
         rec.material = self.material.clone();
+        rec.set_face_normal(r, &self.normal);
+        rec.set_surface_coords(alpha, beta, self.u, self.v);
+
+        rec.is_opaque(rand)
+    }
+}
+
+// Closed box with opposite corners `a` and `b`, made of six quads facing outwards. It needs some extent along every axis.
+pub fn make_box(a: Point, b: Point, material: Box<dyn Material>) -> Arc<HittableList> {
+    let min = Point::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+    let max = Point::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+
+    let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+    let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+    let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+
+    let mut sides = HittableList::new();
+    sides.add(Quad::new(Point::new(min.x, min.y, max.z), dx, dy, material.clone())); // Front
+    sides.add(Quad::new(Point::new(max.x, min.y, max.z), dz * -1.0, dy, material.clone())); // Right
+    sides.add(Quad::new(Point::new(max.x, min.y, min.z), dx * -1.0, dy, material.clone())); // Back
+    sides.add(Quad::new(Point::new(min.x, min.y, min.z), dz, dy, material.clone())); // Left
+    sides.add(Quad::new(Point::new(min.x, max.y, max.z), dx, dz * -1.0, material.clone())); // Top
+    sides.add(Quad::new(Point::new(min.x, min.y, min.z), dx, dz, material)); // Bottom
+
+    Arc::new(sides)
+}
+
+// Fog, smoke and mist filling a closed, convex boundary. Rays scatter off particles at exponentially
+// distributed distances and otherwise pass straight through, which also lets shadow rays through with
+// the right probability.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    density: f32, // Chance of scattering per unit of distance
+    phase: Box<dyn Material>,
+}
 
-        let outward_normal = (rec.point - self.center) / self.radius;
-        rec.set_face_normal(r, &outward_normal);
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f32, phase: Box<dyn Material>) -> Arc<Self> {
+        Arc::new(ConstantMedium { boundary, density: density.max(1e-6), phase })
+    }
+
+    // Part of the interval the ray spends inside the boundary
+    fn overlap(&self, r: &Ray, interval: &Interval, rand: &mut RandomGenerator) -> Option<(f32, f32)> {
+
+        // Find where the ray's line enters and leaves the boundary, even if it starts inside
+        let mut entry = HitRecord::new();
+        let mut exit = HitRecord::new();
+        if !self.boundary.hit(r, Interval::universe(), &mut entry, rand) {
+            return None;
+        }
+        if !self.boundary.hit(r, Interval::new(entry.t + 0.0001, f32::MAX), &mut exit, rand) {
+            return None;
+        }
+
+        let t_min = entry.t.max(interval.min);
+        let t_max = exit.t.min(interval.max);
+        (t_min < t_max).then_some((t_min, t_max))
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        let Some((t_min, t_max)) = self.overlap(r, &interval, rand) else { return false };
+
+        let length = r.direction().length();
+        let distance = -(1.0 - rand.random_float()).ln() / self.density;
+        if distance > (t_max - t_min) * length {
+            return false;
+        }
+
+        rec.t = t_min + distance / length;
+        rec.point = r.at(rec.t);
+        rec.material = self.phase.clone();
+
+        // Particles have no surface, so any frame does
+        let normal = r.direction().unit_vector() * -1.0;
+        rec.set_face_normal(r, &normal);
+        let frame = Onb::new(&normal);
+        rec.set_surface_coords(0.0, 0.0, frame.u, frame.v);
 
         true
     }
+
+    // Beer-Lambert over the part of the ray inside
+    fn transmittance(&self, r: &Ray, interval: Interval, rand: &mut RandomGenerator) -> f32 {
+        match self.overlap(r, &interval, rand) {
+            Some((t_min, t_max)) => (-self.density * (t_max - t_min) * r.direction().length()).exp(),
+            None => 1.0,
+        }
+    }
 }
 
 
@@ -111,7 +425,7 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
         
         let mut temp_rec = HitRecord::new();
         let mut hit_anything = false;
@@ -119,7 +433,8 @@ impl Hittable for HittableList {
 
         for object in self.objects.iter() {
 
-            if object.hit(r, Interval::new(interval.min, closest_so_far), &mut temp_rec) {
+            if object.hit(r, Interval::new(interval.min, closest_so_far), &mut temp_rec, rand) {
+                debug_assert!(!temp_rec.dpdu.near_zero() && !temp_rec.dpdv.near_zero(), "Hittable did not set its surface coordinates");
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
@@ -130,6 +445,17 @@ impl Hittable for HittableList {
         hit_anything
 
     }
+
+    fn transmittance(&self, r: &Ray, interval: Interval, rand: &mut RandomGenerator) -> f32 {
+        let mut transmittance = 1.0;
+        for object in self.objects.iter() {
+            transmittance *= object.transmittance(r, Interval::new(interval.min, interval.max), rand);
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+        }
+        transmittance
+    }
 }
 
 pub struct Interval {
@@ -163,7 +489,190 @@ impl Interval {
     //     Interval { min: f32::MAX, max: f32::MIN }
     // }
 
-    // pub fn universe() -> Interval {
-    //     Interval { min: f32::MIN, max: f32::MAX }
-    // }
-}
\ No newline at end of file
+    pub fn universe() -> Interval {
+        Interval { min: f32::MIN, max: f32::MAX }
+    }
+}
+
+
+// Axis-aligned bounding box, for cheaply turning away rays before an exact intersection test
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    min: Point,
+    max: Point,
+}
+
+impl Aabb {
+    // From any two opposite corners, padded a little so flat shapes still have some thickness
+    pub fn new(a: Point, b: Point) -> Self {
+        let pad = 1e-4;
+        Aabb {
+            min: Point::new(a.x.min(b.x) - pad, a.y.min(b.y) - pad, a.z.min(b.z) - pad),
+            max: Point::new(a.x.max(b.x) + pad, a.y.max(b.y) + pad, a.z.max(b.z) + pad),
+        }
+    }
+
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Self {
+        Aabb {
+            min: Point::new(a.min.x.min(b.min.x), a.min.y.min(b.min.y), a.min.z.min(b.min.z)),
+            max: Point::new(a.max.x.max(b.max.x), a.max.y.max(b.max.y), a.max.z.max(b.max.z)),
+        }
+    }
+
+    // Part of the interval the ray spends inside the box, if any
+    pub fn hit(&self, r: &Ray, interval: &Interval) -> Option<(f32, f32)> {
+        let (mut t_min, mut t_max) = (interval.min, interval.max);
+
+        for (origin, direction, min, max) in [
+            (r.origin().x, r.direction().x, self.min.x, self.max.x),
+            (r.origin().y, r.direction().y, self.min.y, self.max.y),
+            (r.origin().z, r.direction().z, self.min.z, self.max.z),
+        ] {
+            let inverse = 1.0 / direction;
+            let t0 = (min - origin) * inverse;
+            let t1 = (max - origin) * inverse;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some((t_min, t_max))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Point on the unit sphere at the given surface coordinates, the inverse of `get_sphere_uv`
+    fn sphere_point(u: f32, v: f32) -> Point {
+        let (theta, phi) = (v * f32::consts::PI, u * 2.0 * f32::consts::PI);
+        Point::new(-phi.cos() * theta.sin(), -theta.cos(), phi.sin() * theta.sin())
+    }
+
+    fn assert_close(a: &Vec3, b: &Vec3, tolerance: f32) {
+        assert!((*a - *b).length() < tolerance, "{a:?} against {b:?}");
+    }
+
+    #[test]
+    fn sphere_uv_follows_axes() {
+        let sphere = Sphere::new(Point::new(0.0, 0.0, 0.0), 1.0, Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let uv = |p: Point| {
+            let (u, v, _, _) = sphere.get_sphere_uv(&p);
+            (u, v)
+        };
+
+        let (u, v) = uv(Point::new(1.0, 0.0, 0.0));
+        assert!((u - 0.5).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+        let (u, v) = uv(Point::new(0.0, 0.0, 1.0));
+        assert!((u - 0.25).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+        let (u, v) = uv(Point::new(0.0, 0.0, -1.0));
+        assert!((u - 0.75).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+        assert!(uv(Point::new(0.0, -1.0, 0.0)).1.abs() < 1e-6);
+        assert!((uv(Point::new(0.0, 1.0, 0.0)).1 - 1.0).abs() < 1e-6);
+    }
+
+    // The derivatives match finite differences of the surface, scaled by the radius, and span the
+    // tangent plane with their cross product pointing out of the sphere
+    #[test]
+    fn sphere_derivatives_match_surface() {
+        let radius = 2.5;
+        let sphere = Sphere::new(Point::new(0.0, 0.0, 0.0), radius, Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+
+        let h = 1e-3;
+        for (u, v) in [(0.1, 0.3), (0.4, 0.5), (0.7, 0.8), (0.95, 0.2)] {
+            let p = sphere_point(u, v);
+            let (u_hit, v_hit, dpdu, dpdv) = sphere.get_sphere_uv(&p);
+            assert!((u_hit - u).abs() < 1e-4 && (v_hit - v).abs() < 1e-4);
+
+            let du = (sphere_point(u + h, v) - sphere_point(u - h, v)) * (radius / (2.0 * h));
+            let dv = (sphere_point(u, v + h) - sphere_point(u, v - h)) * (radius / (2.0 * h));
+            assert_close(&dpdu, &du, 1e-2 * du.length());
+            assert_close(&dpdv, &dv, 1e-2 * dv.length());
+
+            assert!(Vec3::dot(&Vec3::cross(&dpdu, &dpdv).unit_vector(), &p) > 0.999);
+        }
+    }
+
+    #[test]
+    fn sphere_poles_get_a_tangent_frame() {
+        let sphere = Sphere::new(Point::new(0.0, 0.0, 0.0), 1.0, Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        for p in [Point::new(0.0, 1.0, 0.0), Point::new(0.0, -1.0, 0.0)] {
+            let (_, _, dpdu, dpdv) = sphere.get_sphere_uv(&p);
+            assert!(dpdu.length() > 0.0 && dpdv.length() > 0.0);
+            assert!(Vec3::dot(&dpdu, &p).abs() < 1e-6 && Vec3::dot(&dpdv, &p).abs() < 1e-6);
+            assert!(Vec3::cross(&dpdu, &dpdv).length() > 0.0);
+        }
+    }
+
+    #[test]
+    fn sphere_hit_sets_surface_coordinates() {
+        let sphere = Sphere::new(Point::new(0.0, 0.0, -3.0), 1.0, Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let mut rec = HitRecord::new();
+        assert!(sphere.hit(&ray, Interval::new(0.001, f32::INFINITY), &mut rec, &mut RandomGenerator::seeded(1)));
+        assert!((rec.t - 2.0).abs() < 1e-5);
+        assert!((rec.u - 0.25).abs() < 1e-5 && (rec.v - 0.5).abs() < 1e-5);
+        assert_close(&rec.dpdu, &Vec3::new(2.0 * f32::consts::PI, 0.0, 0.0), 1e-4);
+        assert_close(&rec.dpdv, &Vec3::new(0.0, f32::consts::PI, 0.0), 1e-4);
+    }
+
+    fn grey() -> Box<dyn Material> {
+        Lambertian::new(Color::new(0.5, 0.5, 0.5))
+    }
+
+    fn hit(object: &dyn Hittable, origin: Point, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::new();
+        let ray = Ray::new(origin, direction);
+        object.hit(&ray, Interval::new(0.001, f32::INFINITY), &mut rec, &mut RandomGenerator::seeded(1)).then_some(rec)
+    }
+
+    #[test]
+    fn quad_hits_inside_its_edges() {
+        let quad = Quad::new(Point::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), grey());
+
+        let rec = hit(quad.as_ref(), Point::new(2.5, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0)).expect("hit");
+        assert!((rec.t - 3.0).abs() < 1e-5);
+        assert!((rec.u - 0.5).abs() < 1e-5 && (rec.v - 0.5).abs() < 1e-5);
+        assert!(rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(0.0, 0.0, 1.0), 1e-6);
+        assert_close(&rec.dpdu, &Vec3::new(2.0, 0.0, 0.0), 1e-6);
+
+        // Seen from behind, the normal faces the ray
+        let rec = hit(quad.as_ref(), Point::new(2.5, 0.5, -3.0), Vec3::new(0.0, 0.0, 1.0)).expect("hit");
+        assert!(!rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(0.0, 0.0, -1.0), 1e-6);
+
+        // Outside the parallelogram, though inside its bounding rectangle, and parallel to the plane
+        assert!(hit(quad.as_ref(), Point::new(1.2, 0.9, 3.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(hit(quad.as_ref(), Point::new(2.5, 0.5, 3.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "span no area")]
+    fn quad_rejects_parallel_edges() {
+        Quad::new(Point::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(2.0, 2.0, 0.0), grey());
+    }
+
+    #[test]
+    fn box_sides_face_outwards() {
+        let cube = make_box(Point::new(1.0, 2.0, 3.0), Point::new(-1.0, 0.0, 1.0), grey());
+        let center = Point::new(0.0, 1.0, 2.0);
+
+        let axes = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        for axis in axes.iter().flat_map(|&a| [a, a * -1.0]) {
+            let rec = hit(cube.as_ref(), center + axis * 5.0, axis * -1.0).expect("hit from outside");
+            assert!(rec.front_face, "side {axis:?}");
+            assert_close(&rec.normal, &axis, 1e-6);
+            assert_close(&rec.point, &(center + axis), 1e-5);
+
+            let rec = hit(cube.as_ref(), center, axis).expect("hit from inside");
+            assert!(!rec.front_face, "side {axis:?}");
+            assert_close(&rec.normal, &(axis * -1.0), 1e-6);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use crate::random::RandomGenerator;
+use crate::spectrum::{Spectrum, Wavelengths};
+use crate::util::Vec3;
+
+use Vec3 as Point;
+use Vec3 as Color;
+
+// Light arriving at a point from one sample of a light source
+pub struct LightSample {
+    pub direction: Vec3, // Unit vector towards the light
+    pub distance: f32,   // How far the shadow ray has to reach
+    pub radiance: Color, // Incoming light, already divided by the sampling pdf
+}
+
+// Analytic lights that aren't part of the world geometry, so they are only found with shadow rays
+// In spectral mode the radiance holds one value per path wavelength.
+pub trait Light: Send + Sync {
+    fn sample(&self, point: &Point, wavelengths: Option<&Wavelengths>, rand: &mut RandomGenerator) -> Option<LightSample>;
+}
+
+// Color and strength shared by all light types
+#[derive(Debug, Clone)]
+pub struct Emission {
+    color: Color,
+    intensity: f32,
+    spectrum: Option<Spectrum>, // Replaces daylight as the white the color tints
+}
+
+impl Emission {
+    pub fn new(color: Color, intensity: f32) -> Self {
+        Emission { color, intensity, spectrum: None }
+    }
+
+    // Tints the emission by the spectrum of a black body at the given temperature
+    pub fn with_temperature(self, kelvin: f32) -> Self {
+        self.with_spectrum(Spectrum::blackbody(kelvin))
+    }
+
+    pub fn with_spectrum(self, spectrum: Spectrum) -> Self {
+        Emission { spectrum: Some(spectrum), ..self }
+    }
+
+    fn radiance(&self, wavelengths: Option<&Wavelengths>) -> Color {
+        let radiance = match (wavelengths, &self.spectrum) {
+            (None, None) => self.color,
+            (None, Some(spectrum)) => &self.color * &spectrum.color(),
+            (Some(wavelengths), None) => wavelengths.upsample_illuminant(&self.color),
+            (Some(wavelengths), Some(spectrum)) => &wavelengths.upsample(&self.color) * &wavelengths.spectrum(spectrum),
+        };
+        radiance * self.intensity
+    }
+}
+
+
+// ----------------------------------------------
+// Point light ----------------------------------
+// ----------------------------------------------
+pub struct PointLight {
+    position: Point,
+    radius: f32, // Spreads the light over a sphere for soft shadows
+    emission: Emission,
+}
+
+impl PointLight {
+    pub fn new(position: Point, radius: f32, emission: Emission) -> Arc<Self> {
+        Arc::new(PointLight { position, radius: radius.max(0.0), emission })
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: &Point, wavelengths: Option<&Wavelengths>, rand: &mut RandomGenerator) -> Option<LightSample> {
+        let position = sample_sphere(&self.position, self.radius, rand);
+        let (direction, distance) = towards(point, &position)?;
+
+        Some(LightSample { direction, distance, radiance: self.emission.radiance(wavelengths) / (distance * distance) })
+    }
+}
+
+
+// ----------------------------------------------
+// Spot light -----------------------------------
+// ----------------------------------------------
+pub struct SpotLight {
+    position: Point,
+    direction: Vec3,
+    cos_inner: f32, // Full intensity inside the inner cone
+    cos_outer: f32, // No light outside the outer cone
+    radius: f32,
+    emission: Emission,
+}
+
+impl SpotLight {
+    // Cone angles are the half angles in degrees
+    pub fn new(position: Point, direction: Vec3, inner_angle: f32, outer_angle: f32, radius: f32, emission: Emission) -> Arc<Self> {
+        let outer_angle = outer_angle.max(inner_angle);
+        Arc::new(SpotLight {
+            position,
+            direction: direction.unit_vector(),
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+            radius: radius.max(0.0),
+            emission,
+        })
+    }
+
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t) // Smoothstep between the two cones
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: &Point, wavelengths: Option<&Wavelengths>, rand: &mut RandomGenerator) -> Option<LightSample> {
+        let position = sample_sphere(&self.position, self.radius, rand);
+        let (direction, distance) = towards(point, &position)?;
+
+        let falloff = self.falloff(Vec3::dot(&(direction * -1.0), &self.direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample { direction, distance, radiance: self.emission.radiance(wavelengths) * (falloff / (distance * distance)) })
+    }
+}
+
+
+// ----------------------------------------------
+// Directional light ----------------------------
+// ----------------------------------------------
+pub struct DirectionalLight {
+    direction: Vec3, // Unit vector pointing towards the light
+    cos_angular_radius: f32,
+    emission: Emission,
+}
+
+impl DirectionalLight {
+    // `direction` is the way the light travels. A non-zero angular radius in degrees gives soft shadows.
+    pub fn new(direction: Vec3, angular_radius: f32, emission: Emission) -> Arc<Self> {
+        Arc::new(DirectionalLight {
+            direction: direction.unit_vector() * -1.0,
+            cos_angular_radius: angular_radius.max(0.0).to_radians().cos(),
+            emission,
+        })
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: &Point, wavelengths: Option<&Wavelengths>, rand: &mut RandomGenerator) -> Option<LightSample> {
+        let direction = if self.cos_angular_radius < 1.0 {
+            rand.random_in_cone(&self.direction, self.cos_angular_radius)
+        } else {
+            self.direction
+        };
+
+        Some(LightSample { direction, distance: f32::MAX, radiance: self.emission.radiance(wavelengths) })
+    }
+}
+
+
+// ----------------------------------------------
+// Light list -----------------------------------
+// ----------------------------------------------
+pub struct LightList {
+    lights: Vec<Arc<dyn Light>>,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        LightList { lights: Vec::new() }
+    }
+
+    pub fn add(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Light>> {
+        self.lights.iter()
+    }
+}
+
+fn sample_sphere(center: &Point, radius: f32, rand: &mut RandomGenerator) -> Point {
+    if radius > 0.0 {
+        *center + rand.random_unit_vector_on_sphere() * radius
+    } else {
+        *center
+    }
+}
+
+fn towards(from: &Point, to: &Point) -> Option<(Vec3, f32)> {
+    let offset = *to - *from;
+    let distance = offset.length();
+    if distance <= 0.0 {
+        return None;
+    }
+    Some((offset / distance, distance))
+}
@@ -3,25 +3,58 @@ mod hittable;
 mod camera;
 mod random;
 mod material;
-
+mod environment;
+mod light;
+mod spectrum;
+mod texture;
+mod noise;
+mod microfacet;
+mod volume;
+mod quadric;
+mod scenes;
+
+use std::env;
 use std::sync::Arc;
 
 use camera::Camera;
-use material::{Dielectric, Lambertian, Metal};
-use util::Vec3;
-use hittable::{HittableList, Sphere};
 use random::RandomGenerator;
-
-use Vec3 as Point;
-use Vec3 as Color;
+use texture::WrapMode;
 
 // NOTES --------------------------
 // y-axis go up, the x-axis to the right, and the negative z-axis pointing in the viewing direction
+//
+// Usage: rust-rt [scene] [scene arguments] [--spectral]
+//  - spheres                                     (default)
+//  - hdri <environment.hdr|exr> [intensity] [rotation in degrees]
+//  - sky [hour of the day] [turbidity]
+//  - lights
+//  - textures [image] [repeat|clamp|mirror]
+//  - noise [seed]
+//  - bump [normal map]
+//  - metals
+//  - glass
+//  - dispersion
+//  - spectral
+//  - principled
+//  - clearcoat
+//  - rough-diffuse
+//  - subsurface
+//  - volumes [fog density]
+//  - clouds [grid.raw|slice directory]
+//  - nested
+//  - iridescence
+//  - mix
+//  - cornell
+//  - quadrics
 
 fn main() {
 
     println!(" \n Starting Code \n ");
 
+    // Spectral rendering can be switched on for any scene
+    let spectral = env::args().any(|arg| arg == "--spectral");
+    let args: Vec<String> = env::args().filter(|arg| arg != "--spectral").collect();
+
     // Make random generator
     let mut rand = RandomGenerator::new();
 
@@ -29,62 +62,61 @@ fn main() {
     img_path.push_str(&rand.random_chars(4));
     img_path.push_str(".png");
 
-    // Camera parameters
-    let fov = 20.0;
-    let look_from = Point::new(13.0, 2.0, 3.0);
-    let look_at = Point::new(0.0, 0.0, 0.0);
-
-    // Initialize camera
-    let camera = Camera::initialize(fov, look_from, look_at);
-
-    // Set up image buffer
-    // let img_buf = Arc::new(Mutex::new(image::ImageBuffer::new(camera.img_width as u32, camera.img_height as u32)));
-    let img_buf = image::ImageBuffer::new(camera.img_width as u32, camera.img_height as u32);
-
     // Set up scene
-    let mut world = HittableList::new();
-
-    let mat_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
-    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, mat_ground));
-
-    for a in -22..22{
-        for b in -11..11 {
-            let choose_mat = &rand.random_float();
-
-            let center = Point::new(a as f32 + 0.9 * &rand.random_float(), 0.2, b as f32 + 0.9 * &rand.random_float());
-
-            if (center - Point::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                if *choose_mat < 0.8 {
-                    // diffuse
-                    let albedo = &rand.random_vec3_range(0.0, 1.0) * &rand.random_vec3_range(0.0, 1.0);
-                    let mat = Lambertian::new(albedo);
-                    world.add(Sphere::new(center, 0.2, mat));
-                } else if *choose_mat < 0.95 {
-                    // Metal
-                    let albedo = &rand.random_vec3_range(0.5, 1.0);
-                    let fuzz = &rand.random_float_range(0.0, 0.5);
-                    let mat = Metal::new(*albedo, *fuzz);
-                    world.add(Sphere::new(center, 0.2, mat));
-                } else {
-                    // Glass
-                    let mat = Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.5);
-                    world.add(Sphere::new(center, 0.2, mat));
-                }
-            }
+    let (mut camera, world, lights) = match args.get(1).map(String::as_str) {
+        Some("hdri") => {
+            let env_path = args.get(2).expect("Usage: rust-rt hdri <environment.hdr> [intensity] [rotation]");
+            let intensity = args.get(3).map_or(1.0, |s| s.parse().expect("Intensity must be a number"));
+            let rotation = args.get(4).map_or(0.0, |s| s.parse().expect("Rotation must be a number"));
+            scenes::hdri(env_path, intensity, rotation)
         }
-    }
-
-    let mat_1 = Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.33);
-    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, mat_1));
+        Some("sky") => {
+            let hour = args.get(2).map_or(10.0, |s| s.parse().expect("Hour must be a number"));
+            let turbidity = args.get(3).map_or(3.0, |s| s.parse().expect("Turbidity must be a number"));
+            scenes::sky(hour, turbidity)
+        }
+        Some("lights") => scenes::lights(),
+        Some("glass") => scenes::glass(),
+        Some("dispersion") => scenes::dispersion(),
+        Some("metals") => scenes::metals(),
+        Some("bump") => scenes::bump(args.get(2).map(String::as_str)),
+        Some("noise") => {
+            let seed = args.get(2).map_or(0, |s| s.parse().expect("Seed must be a whole number"));
+            scenes::noise(seed)
+        }
+        Some("textures") => {
+            let wrap = match args.get(3).map(String::as_str) {
+                Some("clamp") => WrapMode::Clamp,
+                Some("mirror") => WrapMode::Mirror,
+                _ => WrapMode::Repeat,
+            };
+            scenes::textures(args.get(2).map(String::as_str), wrap)
+        }
+        Some("spectral") => scenes::spectral(),
+        Some("principled") => scenes::principled(),
+        Some("clearcoat") => scenes::clearcoat(),
+        Some("rough-diffuse") => scenes::rough_diffuse(),
+        Some("subsurface") => scenes::subsurface(),
+        Some("volumes") => {
+            let fog = args.get(2).map_or(0.0, |s| s.parse().expect("Fog density must be a number"));
+            scenes::volumes(fog)
+        }
+        Some("clouds") => scenes::clouds(args.get(2).map(String::as_str)),
+        Some("nested") => scenes::nested(),
+        Some("iridescence") => scenes::iridescence(),
+        Some("mix") => scenes::mix(),
+        Some("cornell") => scenes::cornell(),
+        Some("quadrics") => scenes::quadrics(),
+        _ => scenes::bouncing_spheres(&mut rand),
+    };
+    camera.spectral = spectral;
 
-    let mat_2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
-    world.add(Sphere::new(Point::new(-4.0, 1.0, 0.0), 1.0, mat_2));
+    // Set up image buffer
+    // let img_buf = Arc::new(Mutex::new(image::ImageBuffer::new(camera.img_width as u32, camera.img_height as u32)));
+    let img_buf = image::ImageBuffer::new(camera.img_width, camera.img_height);
 
-    let mat_3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
-    world.add(Sphere::new(Point::new(4.0, 1.0, 0.0), 1.0, mat_3));
-    
     // Render scene
-    Camera::render(Arc::new(camera), Arc::new(world), img_buf, img_path);
+    Camera::render(Arc::new(camera), Arc::new(world), Arc::new(lights), img_buf, img_path);
 
     println!("Raytrace finished.");
 
@@ -1,116 +1,2043 @@
-use crate::hittable::HitRecord;
-use crate::util::{Ray, Vec3};
+use crate::environment::luminance;
+use crate::hittable::{HitRecord, Hittable, Interval};
+use crate::util::{Medium, Onb, Ray, Vec3};
+use crate::microfacet::{conductor_from_reflectance, fresnel_conductor, fresnel_dielectric, fresnel_thin_film, refract, Ggx};
 use crate::random::RandomGenerator;
+use crate::spectrum::{sample_wavelength, spectrum_to_rgb, upsample_rgb, Dispersion, Wavelengths};
+use crate::texture::{SolidColor, Texture};
 
+use std::f32::consts::PI;
 use std::fmt::Debug;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use Vec3 as Color;
 
 pub trait Material: Send + Sync + Debug {
     fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)>;
+
+    // Evaluates scattering towards a given direction for light sampling.
+    // Returns the BSDF times the cosine term, and the pdf `scatter` would have picked that direction with.
+    // Specular materials can't be evaluated this way and return None. Materials with specular and other
+    // lobes evaluate the others, and mark rays they sample from the specular ones with `Ray::specular`.
+    fn eval(&self, _ray_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> Option<(Color, f32)> {
+        None
+    }
+
+    // Light given off at the hit, on top of anything scattered
+    fn emitted(&self, _ray_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // How much of the surface is there at the hit, from 0 where rays pass straight through it to 1
+    fn alpha(&self, _rec: &HitRecord) -> f32 {
+        1.0
+    }
+
+    // Whether `scatter` and `eval` give values at the ray's wavelengths in spectral mode, rather than RGB
+    // for the camera to up-sample. Only thin films vary too quickly over wavelength to go through RGB.
+    fn spectral(&self, _ray_in: &Ray) -> bool {
+        false
+    }
+
     fn clone(&self) -> Box<dyn Material>;
 }
 
+// RGB up-sampled to the ray's wavelengths in spectral mode, for materials that answer spectrally
+fn upsampled(ray_in: &Ray, rgb: &Color) -> Color {
+    ray_in.wavelengths.map_or(*rgb, |wavelengths| wavelengths.upsample(rgb))
+}
+
 
 #[derive(Clone, Debug)]
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(color: Color) -> Box<Self> {
-        Box::new(Lambertian { albedo: color })
+        Lambertian::textured(SolidColor::new(color))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Box<Self> {
+        Box::new(Lambertian { albedo })
     }
 }
 
 impl Material for Lambertian {
     fn scatter(&self, _ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
-        let mut scatter_dir = rec.normal + rand.random_unit_vector_on_sphere();
+        let scatter_dir = Onb::new(&rec.shading_normal).to_world(&rand.random_cosine_direction());
 
-        if scatter_dir.near_zero() {
-            scatter_dir = rec.normal;
+        if Vec3::dot(&scatter_dir, &rec.normal) <= 0.0 {
+            return None; // A perturbed normal sent the ray into the surface
         }
 
         let scattered = Ray::new(rec.point, scatter_dir);
-        let attenuation = self.albedo;
+        let attenuation = rec.lookup(&self.albedo);
 
         Option::Some((scattered, attenuation))
     }
 
+    fn eval(&self, _ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let cosine = Vec3::dot(&rec.shading_normal, &direction.unit_vector());
+        if cosine <= 0.0 || Vec3::dot(&rec.normal, direction) <= 0.0 {
+            return None;
+        }
+
+        let pdf = cosine / PI;
+        Some((rec.lookup(&self.albedo) * pdf, pdf))
+    }
+
     fn clone(&self) -> Box<dyn Material> {
-        Lambertian::new(self.albedo)
+        Lambertian::textured(Arc::clone(&self.albedo))
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Metal {
-    albedo: Color,
-    specular: f32,
+    albedo: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    thin_film: Option<ThinFilm>,
 }
 
 impl Metal {
     pub fn new(color: Color, fuzz: f32) -> Box<Self> {
-        Box::new(Metal { albedo: color , specular: fuzz.max(0.0) })
+        Metal::textured(SolidColor::new(color), SolidColor::scalar(fuzz.max(0.0)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, fuzz: Arc<dyn Texture>) -> Box<Self> {
+        Box::new(Metal { albedo, specular: fuzz, thin_film: None })
+    }
+
+    // Oxide or coating layer, like on heat-tinted titanium. The metal under it gets an index fitted to its albedo.
+    pub fn with_thin_film(mut self: Box<Self>, film: ThinFilm) -> Box<Self> {
+        self.thin_film = Some(film);
+        self
+    }
+
+    // What the surface reflects of the incoming light: the albedo, or with a film over the metal what
+    // the two reflect together at the incoming angle
+    fn reflectance(&self, ray_in: &Ray, rec: &HitRecord) -> Color {
+        let albedo = rec.lookup(&self.albedo);
+        let Some(film) = &self.thin_film else { return albedo };
+
+        let (eta, k) = conductor_from_reflectance(&albedo, &albedo);
+        let cos_theta = -Vec3::dot(&ray_in.direction().unit_vector(), &rec.shading_normal);
+        film.at(ray_in, rec).reflectance(cos_theta, 1.0, &eta, &k)
     }
 }
 
 impl Material for Metal {
     fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
 
-        let mut reflected = Vec3::reflect(&ray_in.direction(), &rec.normal);
-        reflected = reflected.unit_vector() + (rand.random_unit_vector_on_sphere() * self.specular);
-        let scattered = Ray::new(rec.point, reflected);
+        let specular = rec.lookup(&self.specular).x.max(0.0);
+        let mut reflected = Vec3::reflect(&ray_in.direction().unit_vector(), &rec.shading_normal);
+
+        // Only a perfect mirror keeps a narrow footprint, fuzz spreads it over many pixels
+        let scattered = if specular > 0.0 {
+            reflected = reflected + (rand.random_unit_vector_on_sphere() * specular);
+            Ray::new(rec.point, reflected)
+        } else {
+            Ray::with_differentials(rec.point, reflected, rec.reflected_differentials(ray_in))
+        };
 
         match Vec3::dot(scattered.direction(), &rec.normal) > 0.0 {
-            true => Option::Some((scattered, self.albedo)),
+            true => Option::Some((scattered, self.reflectance(ray_in, rec))),
             false => Option::None,
         }
 
     }
 
+    // Fuzz aims at a random point on a sphere of radius `fuzz` around the mirror direction, so the pdf of a
+    // direction is the area of that sphere it crosses over the solid angle, t² / (4π fuzz² |cos|) at each crossing.
+    // A perfect mirror is a delta lobe and can't be evaluated.
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let fuzz = rec.lookup(&self.specular).x.max(0.0);
+        if fuzz <= 0.0 || Vec3::dot(direction, &rec.normal) <= 0.0 {
+            return None;
+        }
+
+        let reflected = Vec3::reflect(&ray_in.direction().unit_vector(), &rec.shading_normal);
+        let cos_theta = Vec3::dot(&direction.unit_vector(), &reflected);
+        let discriminant = cos_theta * cos_theta - (1.0 - fuzz * fuzz);
+        if discriminant <= 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let pdf: f32 = [cos_theta - root, cos_theta + root]
+            .into_iter()
+            .filter(|&t| t > 0.0)
+            .map(|t| t * t / (4.0 * PI * fuzz * root))
+            .sum();
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        // Every sample leaving the surface carries the reflectance, so the BSDF times cosine is it times the pdf
+        Some((self.reflectance(ray_in, rec) * pdf, pdf))
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.thin_film.is_some() && ray_in.wavelengths.is_some()
+    }
+
     fn clone(&self) -> Box<dyn Material> {
-       Metal::new(self.albedo, self.specular)
+       Box::new(Metal { albedo: Arc::clone(&self.albedo), specular: Arc::clone(&self.specular), thin_film: self.thin_film.clone() })
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Dielectric {
-    albedo: Color,
+    albedo: Arc<dyn Texture>, // Color left after travelling `absorption_distance` inside the glass
     refractive_index: f32,
+    absorption_distance: f32,
+    thin_walled: bool, // Sheets and bubbles: light passes straight through both faces at once
+    dispersion: Option<Dispersion>,
+    priority: u32, // Which of two overlapping dielectrics wins, like a glass over the liquid it holds
+    medium_id: u32, // Tells the medium inside apart from those of other dielectrics, kept by clones
+    thin_film: Option<ThinFilm>,
 }
 
+static NEXT_MEDIUM_ID: AtomicU32 = AtomicU32::new(1);
+
 impl Dielectric {
     pub fn new(color: Color, refractive_index: f32) -> Box<Self> {
-        Box::new(Dielectric { albedo: color , refractive_index })
+        Dielectric::textured(SolidColor::new(color), refractive_index)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, refractive_index: f32) -> Box<Self> {
+        let medium_id = NEXT_MEDIUM_ID.fetch_add(1, Ordering::Relaxed);
+        Box::new(Dielectric { albedo, refractive_index, absorption_distance: 1.0, thin_walled: false, dispersion: None, priority: 0, medium_id, thin_film: None })
+    }
+
+    pub fn with_absorption_distance(mut self: Box<Self>, distance: f32) -> Box<Self> {
+        self.absorption_distance = distance.max(1e-4);
+        self
+    }
+
+    pub fn thin_walled(mut self: Box<Self>) -> Box<Self> {
+        self.thin_walled = true;
+        self
+    }
+
+    // Nested dielectrics: where this one overlaps another, the higher priority one is used and
+    // the boundary between them refracts by the ratio of their indices
+    pub fn with_priority(mut self: Box<Self>, priority: u32) -> Box<Self> {
+        self.priority = priority;
+        self
+    }
+
+    // Coated lenses and soap bubbles. On thin-walled dielectrics the film is the whole wall.
+    pub fn with_thin_film(mut self: Box<Self>, film: ThinFilm) -> Box<Self> {
+        self.thin_film = Some(film);
+        self
+    }
+
+    // Replaces the fixed index with one that varies over wavelength
+    pub fn with_dispersion(mut self: Box<Self>, dispersion: Dispersion) -> Box<Self> {
+        self.refractive_index = dispersion.index(587.6);
+        self.dispersion = Some(dispersion);
+        self
+    }
+
+    // Index of refraction for the path, along with the wavelength it is split into. The camera weighs
+    // the path for the split, as that depends on whether it is rendering in RGB or spectrally.
+    fn index_for(&self, ray_in: &Ray, rand: &mut RandomGenerator) -> (f32, Option<f32>) {
+        let Some(dispersion) = self.dispersion else {
+            return (self.refractive_index, ray_in.wavelength);
+        };
+
+        let lambda = ray_in.wavelength
+            .or(ray_in.wavelengths.map(|w| w.hero()))
+            .unwrap_or_else(|| sample_wavelength(rand.random_float()));
+        (dispersion.index(lambda), Some(lambda))
     }
 
-    fn reflectance(&self, cosine: f32, refractive_index: f32) -> f32 {
-        let r0 = ((1.0 - refractive_index) / (1.0 + self.refractive_index)).powi(2);
-        r0 + (1.0 - r0 ) * ((1.0 - cosine).powi(5))
+    // The glass as a medium for paths travelling through it, absorbing so that `albedo` is left after
+    // `absorption_distance`
+    fn medium(&self, rec: &HitRecord, refractive_index: f32) -> Medium {
+        let albedo = rec.lookup(&self.albedo);
+        let coefficient = |a: f32| -a.max(1e-6).ln() / self.absorption_distance;
+        let absorption = Color::new(coefficient(albedo.x), coefficient(albedo.y), coefficient(albedo.z));
+        Medium { id: self.medium_id, priority: self.priority, refractive_index, absorption }
+    }
+
+    fn scatter_thin(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let unit_direction = ray_in.direction().unit_vector();
+        let cos_theta = Vec3::dot(&(unit_direction * -1.0), &rec.shading_normal).min(1.0);
+        let (refractive_index, wavelength) = self.index_for(ray_in, rand);
+
+        let (reflect, weight) = match &self.thin_film {
+            Some(film) => {
+                let reflectance = film.at(ray_in, rec).reflectance(cos_theta, 1.0, &Color::new(1.0, 1.0, 1.0), &Color::new(0.0, 0.0, 0.0));
+                choose_reflection(&reflectance, rand)
+            }
+            None => {
+                // Sum over the light bouncing back and forth between the two faces
+                let r = fresnel_dielectric(cos_theta, refractive_index);
+                let t = 1.0 - r;
+                let reflectance = if r < 1.0 { r + t * t * r / (1.0 - r * r) } else { 1.0 };
+                (reflectance > rand.random_float(), Color::new(1.0, 1.0, 1.0))
+            }
+        };
+
+        let (mut scattered, attenuation) = if reflect {
+            let dir = Vec3::reflect(&unit_direction, &rec.shading_normal);
+            (Ray::with_differentials(rec.point, dir, rec.reflected_differentials(ray_in)), weight)
+        } else {
+            let scattered = Ray::with_differentials(rec.point, unit_direction, ray_in.differentials);
+            let albedo = rec.lookup(&self.albedo);
+            let albedo = if self.spectral(ray_in) { upsampled(ray_in, &albedo) } else { albedo };
+            (scattered, &albedo * &weight)
+        };
+
+        scattered.wavelength = wavelength;
+        Some((scattered, attenuation))
     }
 }
 
+// Smooth glass only scatters into the mirror and refracted directions, delta lobes with no `eval`, so it
+// is lit by rays that find a light rather than by light sampling
 impl Material for Dielectric {
     fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        if self.thin_walled {
+            return self.scatter_thin(ray_in, rec, rand);
+        }
+
+        let (refractive_index, wavelength) = self.index_for(ray_in, rand);
+        let medium = self.medium(rec, refractive_index);
+
+        // Paths that got inside without crossing into the medium, like those starting there, are taken to be in it.
+        // The camera absorbs along each segment by the medium the ray carries, so for them that starts from here.
+        let mut media = ray_in.media.unwrap_or_default();
+        if !rec.front_face && !media.contains(&medium) {
+            media.push(medium);
+        }
+
+        let outside = media.current().copied();
+        let mut inside = media;
+        if rec.front_face { inside.push(medium) } else { inside.remove(&medium) }
+
+        // Overlapped by a medium of higher priority, this boundary isn't really there
+        let real = if rec.front_face {
+            outside.is_none_or(|m| m.priority <= medium.priority)
+        } else {
+            media.current().is_some_and(|m| m.same(&medium))
+        };
+        if !real {
+            let mut scattered = Ray::with_differentials(rec.point, *ray_in.direction(), ray_in.differentials);
+            scattered.wavelength = wavelength;
+            scattered.media = Some(inside);
+            return Some((scattered, Color::new(1.0, 1.0, 1.0)));
+        }
+
+        let index = |m: Option<&Medium>| m.map_or(1.0, |m| m.refractive_index);
+        let eta = index(inside.current()) / index(outside.as_ref());
+        let ri = 1.0 / eta;
+
+        let unit_direction = ray_in.direction().unit_vector();
+        let cos_theta = Vec3::dot(&(unit_direction * -1.0), &rec.shading_normal).min(1.0);
+
+        // Exact Fresnel is 1 under total internal reflection
+        let (reflect, weight) = match &self.thin_film {
+            Some(film) => {
+                let substrate = index(inside.current());
+                let layer = film.at(ray_in, rec);
+                let reflectance = layer.reflectance(cos_theta, index(outside.as_ref()), &Color::new(substrate, substrate, substrate), &Color::new(0.0, 0.0, 0.0));
+                choose_reflection(&reflectance, rand)
+            }
+            None => (fresnel_dielectric(cos_theta, eta) > rand.random_float(), Color::new(1.0, 1.0, 1.0)),
+        };
+
+        let mut scattered = if reflect {
+            let dir = Vec3::reflect(&unit_direction, &rec.shading_normal);
+            let mut scattered = Ray::with_differentials(rec.point, dir, rec.reflected_differentials(ray_in));
+            scattered.media = Some(media);
+            scattered
+        } else {
+            let dir = Vec3::refract(&unit_direction, &rec.shading_normal, ri);
+            let mut scattered = Ray::with_differentials(rec.point, dir, rec.refracted_differentials(ray_in, ri));
+            scattered.media = Some(inside);
+            scattered
+        };
+
+        scattered.wavelength = wavelength;
+        Some((scattered, weight))
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.thin_film.is_some() && ray_in.wavelengths.is_some()
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        Box::new(Dielectric {
+            albedo: Arc::clone(&self.albedo),
+            refractive_index: self.refractive_index,
+            absorption_distance: self.absorption_distance,
+            thin_walled: self.thin_walled,
+            dispersion: self.dispersion,
+            priority: self.priority,
+            medium_id: self.medium_id,
+            thin_film: self.thin_film.clone(),
+        })
+    }
+
+}
+
+
+// ----------------------------------------------
+// Thin film ------------------------------------
+// ----------------------------------------------
+
+// A transparent layer a few hundred nanometres thick, about as thick as the wavelength of light. Light
+// reflecting off its top and bottom interferes, so its reflectance changes colour with thickness and angle.
+#[derive(Clone, Debug)]
+pub struct ThinFilm {
+    thickness: Arc<dyn Texture>, // Blends between the thinnest and thickest parts
+    min_thickness: f32,
+    max_thickness: f32,
+    ior: f32,
+}
+
+// The film at a hit
+#[derive(Clone, Copy, Debug)]
+struct FilmLayer {
+    thickness: f32,
+    ior: f32,
+    wavelength: Option<f32>,
+    wavelengths: Option<Wavelengths>,
+}
+
+impl ThinFilm {
+    // Thickness in nanometres
+    pub fn new(thickness: f32, ior: f32) -> Self {
+        ThinFilm::textured(SolidColor::scalar(1.0), thickness, thickness, ior)
+    }
+
+    // Swirling bubbles and oil slicks, from a texture mapping 0 to `min_thickness` and 1 to `max_thickness`
+    pub fn textured(thickness: Arc<dyn Texture>, min_thickness: f32, max_thickness: f32, ior: f32) -> Self {
+        ThinFilm { thickness, min_thickness: min_thickness.max(0.0), max_thickness: max_thickness.max(0.0), ior }
+    }
+
+    fn at(&self, ray_in: &Ray, rec: &HitRecord) -> FilmLayer {
+        let t = rec.lookup(&self.thickness).x.clamp(0.0, 1.0);
+        FilmLayer {
+            thickness: self.min_thickness + t * (self.max_thickness - self.min_thickness),
+            ior: self.ior,
+            wavelength: ray_in.wavelength,
+            wavelengths: ray_in.wavelengths,
+        }
+    }
+}
+
+impl FilmLayer {
+    // Reflectance over a substrate with complex index eta + i k, seen from a medium of index `outside`.
+    // Interference varies too quickly over wavelength to go through RGB, so it is evaluated exactly for
+    // paths that follow a single wavelength and at each of a spectral path's wavelengths, and otherwise
+    // integrated over the spectrum. That integral can come out slightly negative in a channel.
+    fn reflectance(&self, cos_theta: f32, outside: f32, eta: &Color, k: &Color) -> Color {
+        let at = |lambda: f32| {
+            fresnel_thin_film(cos_theta, outside, self.ior, self.thickness, upsample_rgb(eta, lambda), upsample_rgb(k, lambda), lambda)
+        };
+
+        match (self.wavelength, self.wavelengths) {
+            (Some(lambda), _) => {
+                let r = at(lambda);
+                Color::new(r, r, r)
+            }
+            (None, Some(wavelengths)) => wavelengths.map(at),
+            (None, None) => {
+                let rgb = spectrum_to_rgb(at);
+                Color::new(rgb.x.clamp(0.0, 1.0), rgb.y.clamp(0.0, 1.0), rgb.z.clamp(0.0, 1.0))
+            }
+        }
+    }
+}
+
+// Picks reflection or transmission in proportion to a coloured reflectance, with the weight that makes up for it
+fn choose_reflection(reflectance: &Color, rand: &mut RandomGenerator) -> (bool, Color) {
+    let white = Color::new(1.0, 1.0, 1.0);
+    let p = ((reflectance.x + reflectance.y + reflectance.z) / 3.0).clamp(0.0, 1.0);
+    if rand.random_float() < p {
+        (true, *reflectance / p)
+    } else {
+        (false, (white - *reflectance) / (1.0 - p))
+    }
+}
+
+
+// ----------------------------------------------
+// Oren-Nayar -----------------------------------
+// ----------------------------------------------
+
+// Rough diffuse surfaces like clay, concrete and fabric, modelled as V-shaped Lambertian grooves whose
+// slopes have a standard deviation of `sigma`. They scatter more light back towards its source than
+// Lambertian surfaces do, which flattens the look of round objects.
+#[derive(Debug)]
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture>,
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayar {
+    // `sigma` is in degrees, where 0 is Lambertian
+    pub fn new(color: Color, sigma: f32) -> Box<Self> {
+        OrenNayar::textured(SolidColor::new(color), sigma)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, sigma: f32) -> Box<Self> {
+        let sigma2 = sigma.to_radians().powi(2);
+        let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        Box::new(OrenNayar { albedo, a, b })
+    }
+
+    // Reflectance relative to a white Lambertian surface, in the local frame
+    fn factor(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        if sin_o < 1e-4 || sin_i < 1e-4 {
+            return self.a;
+        }
+
+        let cos_phi = ((wo.x * wi.x + wo.y * wi.y) / (sin_o * sin_i)).max(0.0);
+        let (sin_alpha, tan_beta) = if wi.z > wo.z { (sin_o, sin_i / wi.z) } else { (sin_i, sin_o / wo.z) };
+        self.a + self.b * cos_phi * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let frame = Onb::new(&rec.shading_normal);
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        let wi = rand.random_cosine_direction();
+
+        let direction = frame.to_world(&wi);
+        if wo.z <= 0.0 || Vec3::dot(&direction, &rec.normal) <= 0.0 {
+            return None;
+        }
+
+        // Cosine sampling cancels everything but the albedo and the roughness factor
+        let attenuation = rec.lookup(&self.albedo) * self.factor(&wo, &wi);
+        Some((Ray::new(rec.point, direction), attenuation))
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let frame = Onb::new(&rec.shading_normal);
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        let wi = frame.to_local(&direction.unit_vector());
+        if wo.z <= 0.0 || wi.z <= 0.0 || Vec3::dot(&rec.normal, direction) <= 0.0 {
+            return None;
+        }
+
+        let pdf = wi.z / PI;
+        Some((rec.lookup(&self.albedo) * (self.factor(&wo, &wi) * pdf), pdf))
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        Box::new(OrenNayar { albedo: Arc::clone(&self.albedo), a: self.a, b: self.b })
+    }
+}
+
+
+// ----------------------------------------------
+// Microfacet conductor -------------------------
+// ----------------------------------------------
+
+// Rough metal using the GGX distribution with visible normal sampling and Smith masking-shadowing.
+// The complex index of refraction (eta + i k) is given per color channel.
+#[derive(Debug)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    roughness: Arc<dyn Texture>,
+    anisotropy: f32, // 0 is isotropic, towards 1 stretches highlights along dpdu
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f32, anisotropy: f32) -> Box<Self> {
+        Conductor::textured(eta, k, SolidColor::scalar(roughness), anisotropy)
+    }
+
+    pub fn textured(eta: Color, k: Color, roughness: Arc<dyn Texture>, anisotropy: f32) -> Box<Self> {
+        Box::new(Conductor { eta, k, roughness, anisotropy })
+    }
+
+    // Measured optical constants sampled at red, green and blue wavelengths
+    pub fn gold(roughness: f32) -> Box<Self> {
+        Conductor::new(Color::new(0.143, 0.374, 1.442), Color::new(3.983, 2.385, 1.603), roughness, 0.0)
+    }
+
+    pub fn silver(roughness: f32) -> Box<Self> {
+        Conductor::new(Color::new(0.155, 0.117, 0.138), Color::new(4.828, 3.122, 2.147), roughness, 0.0)
+    }
+
+    pub fn copper(roughness: f32) -> Box<Self> {
+        Conductor::new(Color::new(0.200, 0.924, 1.102), Color::new(3.912, 2.452, 2.142), roughness, 0.0)
+    }
+
+    pub fn aluminium(roughness: f32) -> Box<Self> {
+        Conductor::new(Color::new(1.657, 0.880, 0.521), Color::new(9.224, 6.270, 4.837), roughness, 0.0)
+    }
+
+    fn distribution(&self, rec: &HitRecord) -> Ggx {
+        Ggx::from_roughness(rec.lookup(&self.roughness).x, self.anisotropy)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let ggx = self.distribution(rec);
+        let wm = ggx.sample_visible_normal(&wo, rand.random_float(), rand.random_float());
+        let wi = Vec3::reflect(&(wo * -1.0), &wm);
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        let direction = frame.to_world(&wi);
+        if Vec3::dot(&direction, &rec.normal) <= 0.0 {
+            return None;
+        }
+
+        // With visible normal sampling most terms cancel, leaving F * G2 / G1
+        let fresnel = fresnel_conductor(Vec3::dot(&wo, &wm), &self.eta, &self.k);
+        let attenuation = fresnel * (ggx.g2(&wo, &wi) / ggx.g1(&wo));
+
+        Some((Ray::new(rec.point, direction), attenuation))
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        let wi = frame.to_local(&direction.unit_vector());
+        if Vec3::dot(&rec.normal, direction) <= 0.0 {
+            return None;
+        }
+
+        let (value, pdf, wm) = self.distribution(rec).eval_reflection(&wo, &wi)?;
+        let fresnel = fresnel_conductor(Vec3::dot(&wo, &wm), &self.eta, &self.k);
+
+        Some((fresnel * value, pdf))
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        Conductor::textured(self.eta, self.k, Arc::clone(&self.roughness), self.anisotropy)
+    }
+}
+
+
+// ----------------------------------------------
+// Microfacet dielectric ------------------------
+// ----------------------------------------------
+
+// Frosted glass and rough plastics: GGX reflection and transmission (Walter et al. 2007),
+// choosing between the two by the Fresnel term of the sampled microfacet
+#[derive(Debug)]
+pub struct RoughDielectric {
+    refractive_index: f32,
+    roughness: Arc<dyn Texture>,
+}
+
+impl RoughDielectric {
+    pub fn new(refractive_index: f32, roughness: f32) -> Box<Self> {
+        RoughDielectric::textured(refractive_index, SolidColor::scalar(roughness))
+    }
+
+    pub fn textured(refractive_index: f32, roughness: Arc<dyn Texture>) -> Box<Self> {
+        Box::new(RoughDielectric { refractive_index, roughness })
+    }
+
+    // Index on the far side of the surface over the index on the side the ray came from. Only smooth
+    // dielectrics are tracked as media, so the medium the path is in surrounds rough ones on both sides.
+    fn relative_index(&self, ray_in: &Ray, rec: &HitRecord) -> f32 {
+        let outside = ray_in.medium_index();
+        if rec.front_face { self.refractive_index / outside } else { outside / self.refractive_index }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
 
-        let ri = if rec.front_face { 1.0 / self.refractive_index } else { self.refractive_index };
-            
-        let unit_direction = ray_in.direction().unit_vector() * -1.0;
+        let ggx = Ggx::from_roughness(rec.lookup(&self.roughness).x, 0.0);
+        let (wi, weight) = ggx.sample_dielectric(&wo, self.relative_index(ray_in, rec), rand.random_float(), rand.random_float(), rand.random_float())?;
+        let direction = frame.to_world(&wi);
 
-        let cos_theta = Vec3::dot(&unit_direction, &rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
+        // Reflections have to stay above the geometric surface, and transmissions below it
+        if (Vec3::dot(&direction, &rec.normal) > 0.0) != (wi.z > 0.0) {
+            return None;
+        }
+
+        Some((Ray::new(rec.point, direction), Color::new(weight, weight, weight)))
+    }
 
-        let cannot_refract = ri * sin_theta > 1.0;
-        let dir = if cannot_refract || self.reflectance(cos_theta, ri) > rand.random_float_range(0.0, 1.0)
-            {Vec3::reflect(&unit_direction, &rec.normal)} else 
-            {Vec3::refract(&unit_direction, &rec.normal, ri)};
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        let wi = frame.to_local(&direction.unit_vector());
+        if (Vec3::dot(direction, &rec.normal) > 0.0) != (wi.z > 0.0) {
+            return None;
+        }
 
-        Some((Ray::new(rec.point, dir), Color::new(1.0, 1.0, 1.0))) // TODO: Fix this to use the albedo
-        
+        let ggx = Ggx::from_roughness(rec.lookup(&self.roughness).x, 0.0);
+        let (value, pdf) = ggx.eval_dielectric(&wo, &wi, self.relative_index(ray_in, rec))?;
+        Some((Color::new(value, value, value), pdf))
     }
 
     fn clone(&self) -> Box<dyn Material> {
-        Dielectric::new(self.albedo, self.refractive_index)
+        RoughDielectric::textured(self.refractive_index, Arc::clone(&self.roughness))
+    }
+}
+
+
+// ----------------------------------------------
+// Principled ----------------------------------
+// ----------------------------------------------
+
+// Parameters of the principled material, all in [0, 1] apart from the index of refraction
+#[derive(Debug, Clone)]
+pub struct PrincipledParams {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>, // Scales the dielectric Fresnel reflection
+    pub transmission: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_color: Arc<dyn Texture>,
+    pub subsurface: Arc<dyn Texture>, // Flattens the diffuse lobe like light scattered below the surface
+    pub ior: f32,
+    pub thin_film: Option<ThinFilm>, // Iridescent layer on the metal and specular lobes
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        PrincipledParams {
+            base_color: SolidColor::scalar(0.8),
+            metallic: SolidColor::scalar(0.0),
+            roughness: SolidColor::scalar(0.5),
+            specular: SolidColor::scalar(1.0),
+            transmission: SolidColor::scalar(0.0),
+            clearcoat: SolidColor::scalar(0.0),
+            clearcoat_roughness: SolidColor::scalar(0.05),
+            sheen: SolidColor::scalar(0.0),
+            sheen_color: SolidColor::scalar(1.0),
+            subsurface: SolidColor::scalar(0.0),
+            ior: 1.5,
+            thin_film: None,
+        }
     }
+}
+
+// One material for most surfaces, in the spirit of the Disney and OpenPBR models. From the top it is a
+// clearcoat over either a metal or a dielectric base, and the dielectric either transmits light or covers
+// a diffuse layer with sheen. Each layer passes on what its Fresnel term doesn't reflect, so nothing
+// reflects more light than arrives.
+#[derive(Debug)]
+pub struct Principled {
+    params: PrincipledParams,
+    specular_albedo: Arc<AlbedoTable>,
+    interfaces: Arc<[Interface; 2]>, // Seen from outside and from inside
+}
+
+// Rough boundary of a transmissive object, with how much it reflects on average when smooth
+#[derive(Debug)]
+struct Interface {
+    albedo: AlbedoTable,
+    fresnel: f32,
+}
+
+impl Interface {
+    fn new(eta: f32) -> Self {
+        let n = 64;
+        let fresnel: f32 = (0..n).map(|i| fresnel_dielectric(((i as f32 + 0.5) / n as f32).sqrt(), eta)).sum();
+        Interface { albedo: AlbedoTable::interface(eta), fresnel: fresnel / n as f32 }
+    }
+
+    // Both sides of a boundary with the given index inside
+    fn pair(ior: f32) -> Arc<[Interface; 2]> {
+        static PAIRS: OnceLock<Mutex<HashMap<u32, Arc<[Interface; 2]>>>> = OnceLock::new();
+        per_ior(&PAIRS, ior, || [Interface::new(ior), Interface::new(1.0 / ior)])
+    }
+}
+
+// Tables that only depend on the index of refraction are built once per index and shared by every
+// material using it, rather than integrated again for each object
+fn per_ior<T>(cache: &OnceLock<Mutex<HashMap<u32, Arc<T>>>>, ior: f32, build: impl FnOnce() -> T) -> Arc<T> {
+    let mut tables = cache.get_or_init(Default::default).lock().unwrap();
+    Arc::clone(tables.entry(ior.to_bits()).or_insert_with(|| Arc::new(build())))
+}
+
+// Share of the light bouncing between microfacets that finally leaves, given the average Fresnel
+// reflectance and the average single scattering albedo
+fn multiple_fresnel(fresnel: f32, albedo: f32) -> f32 {
+    fresnel * fresnel * albedo / (1.0 - fresnel * (1.0 - albedo))
+}
+
+// Parameters looked up at a hit, with the chance of sampling each lobe
+struct PrincipledLobes<'a> {
+    base_color: Color,
+    sheen_color: Color,
+    subsurface: f32,
+    roughness: f32,
+    ior: f32,
+    eta: f32, // Relative index across the surface, flipped when leaving a transmissive object
+    film: Option<(FilmLayer, (Color, Color))>, // With the index of a metal fitted to the RGB base colour
+    ggx: Ggx,
+    coat_ggx: Ggx,
+    interface: &'a Interface,
+
+    // Light the single scattering reflection and interface lobes miss, which is also how often
+    // those lobes sample their multiple scattering part instead
+    reflection_missing: f32,
+    interface_missing: f32,
+
+    // Weights of each layer, from the Fresnel terms towards the viewer
+    coat: f32,
+    metal: f32,
+    specular: f32,
+    diffuse: f32,
+    transmission: f32,
+
+    // Sampling probabilities, summing to one
+    probabilities: [f32; 5],
+}
+
+impl Principled {
+    const COAT_IOR: f32 = 1.5;
+
+    pub fn new(params: PrincipledParams) -> Box<Self> {
+        let specular_albedo = AlbedoTable::dielectric(params.ior);
+        let interfaces = Interface::pair(params.ior);
+        Box::new(Principled { params, specular_albedo, interfaces })
+    }
+
+    // Interface tables for the object's index over that of the medium around it, which the path is in
+    // on either side of the surface as only smooth dielectrics are tracked as media
+    fn interfaces(&self, ray_in: &Ray) -> (f32, Arc<[Interface; 2]>) {
+        let relative = self.params.ior / ray_in.medium_index();
+        if relative == self.params.ior {
+            (relative, Arc::clone(&self.interfaces))
+        } else {
+            (relative, Interface::pair(relative))
+        }
+    }
+
+    fn lobes<'a>(&self, ray_in: &Ray, rec: &HitRecord, wo: &Vec3, relative_ior: f32, interfaces: &'a [Interface; 2]) -> PrincipledLobes<'a> {
+        let p = &self.params;
+        let scalar = |texture: &Arc<dyn Texture>| rec.lookup(texture).x.clamp(0.0, 1.0);
+
+        let base_color = rec.lookup(&p.base_color);
+        let roughness = scalar(&p.roughness);
+        let metallic = scalar(&p.metallic);
+        let transmission = scalar(&p.transmission);
+        let clearcoat = scalar(&p.clearcoat);
+        let specular = scalar(&p.specular);
+        let sheen = scalar(&p.sheen);
+        let interface = &interfaces[if rec.front_face { 0 } else { 1 }];
+
+        // With a film the lobes are evaluated at the ray's wavelengths, so the colours are too
+        let spectral = self.spectral(ray_in);
+        let convert = |rgb: Color| if spectral { upsampled(ray_in, &rgb) } else { rgb };
+
+        let mut lobes = PrincipledLobes {
+            base_color: convert(base_color),
+            sheen_color: convert(rec.lookup(&p.sheen_color) * sheen),
+            subsurface: scalar(&p.subsurface),
+            roughness,
+            ior: p.ior,
+            eta: if rec.front_face { relative_ior } else { 1.0 / relative_ior },
+            film: p.thin_film.as_ref().map(|film| (film.at(ray_in, rec), conductor_from_reflectance(&base_color, &base_color))),
+            ggx: Ggx::from_roughness(roughness, 0.0),
+            coat_ggx: Ggx::from_roughness(scalar(&p.clearcoat_roughness), 0.0),
+            interface,
+            reflection_missing: 1.0 - AlbedoTable::conductor().lookup(wo.z, roughness),
+            interface_missing: 1.0 - interface.albedo.lookup(wo.z, roughness),
+            coat: 0.0,
+            metal: 0.0,
+            specular: 0.0,
+            diffuse: 0.0,
+            transmission: 1.0,
+            probabilities: [0.0, 0.0, 0.0, 0.0, 1.0],
+        };
+
+        // Inside a transmissive object only the interface itself is left
+        if !rec.front_face {
+            return lobes;
+        }
+
+        let coat = clearcoat * AlbedoTable::coat().lookup(wo.z, scalar(&p.clearcoat_roughness));
+        let below = 1.0 - coat;
+        let dielectric = below * (1.0 - metallic);
+        let opaque = dielectric * (1.0 - transmission);
+        let bounced = multiple_fresnel(lobes.interface.fresnel, AlbedoTable::conductor().average(roughness));
+        let reflected = specular * (self.specular_albedo.lookup(wo.z, roughness) + bounced * lobes.reflection_missing);
+
+        lobes.coat = clearcoat;
+        lobes.metal = below * metallic;
+        lobes.specular = opaque * specular;
+        lobes.diffuse = opaque * (1.0 - reflected);
+        lobes.transmission = dielectric * transmission;
+
+        // Pick lobes roughly by how much light each of them reflects
+        let estimates = [
+            coat,
+            lobes.metal * (0.5 + 0.5 * luminance(&base_color)),
+            opaque * reflected,
+            lobes.diffuse * (luminance(&base_color) + luminance(&lobes.sheen_color)).min(1.0),
+            lobes.transmission,
+        ];
+        let total: f32 = estimates.iter().sum();
+        if total > 0.0 {
+            lobes.probabilities = estimates.map(|estimate| estimate / total);
+        }
+        lobes
+    }
+
+    // BSDF * cos and the combined pdf of all lobes, in the local shading frame
+    fn eval_local(lobes: &PrincipledLobes, wo: &Vec3, wi: &Vec3) -> (Color, f32) {
+        let [p_coat, p_metal, p_specular, p_diffuse, p_transmission] = lobes.probabilities;
+        let mut value = Color::new(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+
+        if wi.z > 0.0 {
+            if p_coat > 0.0 && let Some((reflection, lobe_pdf, wm)) = lobes.coat_ggx.eval_reflection(wo, wi) {
+                let fresnel = fresnel_dielectric(Vec3::dot(wo, &wm), Principled::COAT_IOR);
+                value = value + Color::new(1.0, 1.0, 1.0) * (lobes.coat * fresnel * reflection);
+                pdf += p_coat * lobe_pdf;
+            }
+
+            // Metal and specular share the GGX lobe and its multiple scattering part
+            let single = lobes.ggx.eval_reflection(wo, wi);
+            let multiple = AlbedoTable::conductor().multiple_scattering(wo.z, wi.z, lobes.roughness);
+            let lobe_pdf = (1.0 - lobes.reflection_missing) * single.map_or(0.0, |(_, pdf, _)| pdf)
+                + lobes.reflection_missing * wi.z / PI;
+            let average = AlbedoTable::conductor().average(lobes.roughness);
+
+            if p_metal > 0.0 {
+                if let Some((reflection, _, wm)) = single {
+                    let cos_theta = Vec3::dot(wo, &wm);
+                    let fresnel = match &lobes.film {
+                        Some((film, (eta, k))) => film.reflectance(cos_theta, 1.0, eta, k),
+                        None => {
+                            let schlick = (1.0 - cos_theta).max(0.0).powi(5);
+                            lobes.base_color + (Color::new(1.0, 1.0, 1.0) - lobes.base_color) * schlick
+                        }
+                    };
+                    value = value + fresnel * (lobes.metal * reflection);
+                }
+
+                // Schlick's approximation averages to F0 + (1 - F0) / 21 over the hemisphere
+                let fresnel = lobes.base_color + (Color::new(1.0, 1.0, 1.0) - lobes.base_color) / 21.0;
+                let bounced = Color::new(
+                    multiple_fresnel(fresnel.x, average),
+                    multiple_fresnel(fresnel.y, average),
+                    multiple_fresnel(fresnel.z, average),
+                );
+                value = value + bounced * (lobes.metal * multiple);
+                pdf += p_metal * lobe_pdf;
+            }
+
+            if p_specular > 0.0 {
+                if let Some((reflection, _, wm)) = single {
+                    let cos_theta = Vec3::dot(wo, &wm);
+                    let fresnel = match &lobes.film {
+                        Some((film, _)) => film.reflectance(cos_theta, 1.0, &Color::new(lobes.ior, lobes.ior, lobes.ior), &Color::new(0.0, 0.0, 0.0)),
+                        None => Color::new(1.0, 1.0, 1.0) * fresnel_dielectric(cos_theta, lobes.ior),
+                    };
+                    value = value + fresnel * (lobes.specular * reflection);
+                }
+
+                let bounced = multiple_fresnel(lobes.interface.fresnel, average);
+                value = value + Color::new(1.0, 1.0, 1.0) * (lobes.specular * bounced * multiple);
+                pdf += p_specular * lobe_pdf;
+            }
+
+            if p_diffuse > 0.0 {
+                value = value + Principled::diffuse(lobes, wo, wi) * lobes.diffuse;
+                pdf += p_diffuse * wi.z / PI;
+            }
+        }
+
+        if p_transmission > 0.0 {
+            // Light entering the object is tinted by the base color
+            let tint = if wi.z < 0.0 && lobes.eta > 1.0 { lobes.base_color } else { Color::new(1.0, 1.0, 1.0) };
+            let mut lobe_pdf = 0.0;
+
+            if let Some((interface, single_pdf)) = lobes.ggx.eval_dielectric(wo, wi, lobes.eta) {
+                value = value + tint * (lobes.transmission * interface);
+                lobe_pdf += (1.0 - lobes.interface_missing) * single_pdf;
+            }
+
+            // Light bouncing inside the rough interface leaves on either side, split by the average
+            // Fresnel term, and like single scattering is compressed by 1 / eta^2 when transmitted
+            let (share, radiance) = if wi.z > 0.0 {
+                (lobes.interface.fresnel, 1.0)
+            } else {
+                (1.0 - lobes.interface.fresnel, 1.0 / (lobes.eta * lobes.eta))
+            };
+            let multiple = lobes.interface.albedo.multiple_scattering(wo.z, wi.z, lobes.roughness);
+            value = value + tint * (lobes.transmission * share * radiance * multiple);
+            lobe_pdf += lobes.interface_missing * share * wi.z.abs() / PI;
+            pdf += p_transmission * lobe_pdf;
+        }
+
+        (value, pdf)
+    }
+
+    // Diffuse base with sheen on top, times the cosine
+    fn diffuse(lobes: &PrincipledLobes, wo: &Vec3, wi: &Vec3) -> Color {
+        let wh = (*wo + *wi).unit_vector();
+        let cos_d = Vec3::dot(wi, &wh);
+
+        // Burley's subsurface approximation, rescaled so it reflects as much light as a Lambertian surface
+        let flat = subsurface_shape(wo, wi, lobes.roughness) / AlbedoTable::subsurface().lookup(wo.z, lobes.roughness);
+        let shape = 1.0 + lobes.subsurface * (flat - 1.0);
+
+        // Sheen reflects at grazing angles, and what it reflects doesn't reach the base
+        let sheen = lobes.sheen_color * (1.0 - cos_d).max(0.0).powi(5);
+        let under = Color::new(1.0, 1.0, 1.0) - lobes.sheen_color * AlbedoTable::sheen().lookup(wo.z, 0.0);
+
+        ((&lobes.base_color * &under) * (shape / PI) + sheen) * wi.z
+    }
+}
+
+// Flattened diffuse lobe from Burley's 2012 model, relative to Lambertian
+fn subsurface_shape(wo: &Vec3, wi: &Vec3, roughness: f32) -> f32 {
+    let cos_d = Vec3::dot(wi, &(*wo + *wi).unit_vector());
+    let schlick = |cos: f32| (1.0 - cos).max(0.0).powi(5);
+
+    let fss90 = roughness * cos_d * cos_d;
+    let fss = (1.0 + (fss90 - 1.0) * schlick(wi.z)) * (1.0 + (fss90 - 1.0) * schlick(wo.z));
+    1.25 * (fss * (1.0 / (wi.z + wo.z) - 0.5) + 0.5)
+}
+
+// Directional albedo of a lobe, tabulated over the cosine of the view angle and roughness. Layers use it
+// to hand exactly what they don't reflect on to the layers below.
+#[derive(Debug)]
+struct AlbedoTable {
+    values: [[f32; AlbedoTable::SIZE]; AlbedoTable::SIZE],
+    averages: [f32; AlbedoTable::SIZE], // Cosine weighted average over all view angles, per roughness
+}
+
+impl AlbedoTable {
+    const SIZE: usize = 16;
 
-}
\ No newline at end of file
+    fn new(albedo: impl Fn(&Vec3, f32) -> f32) -> Self {
+        let last = (AlbedoTable::SIZE - 1) as f32;
+        let values = std::array::from_fn(|i| {
+            let cos_o = (i as f32 / last).max(0.01);
+            let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+            std::array::from_fn(|j| albedo(&wo, j as f32 / last))
+        });
+
+        // Averaged through `lookup`, so the averages match the interpolated albedo exactly
+        let mut table = AlbedoTable { values, averages: [0.0; AlbedoTable::SIZE] };
+        table.averages = std::array::from_fn(|j| {
+            let n = 64;
+            let sum: f32 = (0..n).map(|i| table.lookup(((i as f32 + 0.5) / n as f32).sqrt(), j as f32 / last)).sum();
+            sum / n as f32
+        });
+        table
+    }
+
+    // Average of a quantity over the visible normals of GGX microfacets, on a grid
+    fn visible_normal_average(f: impl Fn(&Ggx, &Vec3, &Vec3) -> f32) -> Self {
+        const N: usize = 32;
+        AlbedoTable::new(|wo, roughness| {
+            let ggx = Ggx::from_roughness(roughness, 0.0);
+            let mut sum = 0.0;
+            for a in 0..N {
+                for b in 0..N {
+                    let wm = ggx.sample_visible_normal(wo, (a as f32 + 0.5) / N as f32, (b as f32 + 0.5) / N as f32);
+                    sum += f(&ggx, wo, &wm);
+                }
+            }
+            sum / (N * N) as f32
+        })
+    }
+
+    // GGX reflection off a dielectric
+    fn dielectric(ior: f32) -> Arc<Self> {
+        static TABLES: OnceLock<Mutex<HashMap<u32, Arc<AlbedoTable>>>> = OnceLock::new();
+        per_ior(&TABLES, ior, || AlbedoTable::visible_normal_average(|ggx, wo, wm| {
+            let wi = Vec3::reflect(&(*wo * -1.0), wm);
+            if wi.z <= 0.0 {
+                return 0.0;
+            }
+            fresnel_dielectric(Vec3::dot(wo, wm), ior) * ggx.g2(wo, &wi) / ggx.g1(wo)
+        }))
+    }
+
+    // The clearcoat of every principled material has the same index
+    fn coat() -> &'static Self {
+        static TABLE: OnceLock<Arc<AlbedoTable>> = OnceLock::new();
+        TABLE.get_or_init(|| AlbedoTable::dielectric(Principled::COAT_IOR))
+    }
+
+    // GGX reflection off a perfect mirror, which only loses light to the microfacets shadowing each other
+    fn conductor() -> &'static Self {
+        static TABLE: OnceLock<AlbedoTable> = OnceLock::new();
+        TABLE.get_or_init(|| AlbedoTable::visible_normal_average(|ggx, wo, wm| {
+            let wi = Vec3::reflect(&(*wo * -1.0), wm);
+            if wi.z <= 0.0 {
+                return 0.0;
+            }
+            ggx.g2(wo, &wi) / ggx.g1(wo)
+        }))
+    }
+
+    // Light reflected or transmitted by a rough dielectric interface, counting energy rather than
+    // radiance so it doesn't depend on which way the light crosses
+    fn interface(eta: f32) -> Self {
+        AlbedoTable::visible_normal_average(|ggx, wo, wm| {
+            let fresnel = fresnel_dielectric(Vec3::dot(wo, wm), eta);
+            let mut albedo = 0.0;
+
+            let wr = Vec3::reflect(&(*wo * -1.0), wm);
+            if wr.z > 0.0 {
+                albedo += fresnel * ggx.g2(wo, &wr) / ggx.g1(wo);
+            }
+            if let Some(wt) = refract(wo, wm, eta) && wt.z < 0.0 {
+                albedo += (1.0 - fresnel) * ggx.g2(wo, &wt) / ggx.g1(wo);
+            }
+            albedo
+        })
+    }
+
+    fn sheen() -> &'static Self {
+        static TABLE: OnceLock<AlbedoTable> = OnceLock::new();
+        TABLE.get_or_init(|| {
+            // The sheen lobe isn't divided by PI
+            let albedo = AlbedoTable::hemisphere_integral(|wo, wi| PI * (1.0 - Vec3::dot(wi, &(*wo + *wi).unit_vector())).powi(5));
+            AlbedoTable::new(|wo, _| albedo(wo))
+        })
+    }
+
+    fn subsurface() -> &'static Self {
+        static TABLE: OnceLock<AlbedoTable> = OnceLock::new();
+        TABLE.get_or_init(|| AlbedoTable::new(|wo, roughness| {
+            AlbedoTable::hemisphere_integral(|wo, wi| subsurface_shape(wo, wi, roughness))(wo)
+        }))
+    }
+
+    // Midpoint rule over the hemisphere in cos^2 theta and phi, where the cosine weighted measure is
+    // uniform. Gives the albedo of a lobe that is `f / PI` times the cosine.
+    fn hemisphere_integral(f: impl Fn(&Vec3, &Vec3) -> f32) -> impl Fn(&Vec3) -> f32 {
+        move |wo| {
+            let (n_theta, n_phi) = (32, 64);
+            let mut sum = 0.0;
+            for t in 0..n_theta {
+                let cos2_i = (t as f32 + 0.5) / n_theta as f32;
+                let (cos_i, sin_i) = (cos2_i.sqrt(), (1.0 - cos2_i).sqrt());
+                for p in 0..n_phi {
+                    let phi = 2.0 * PI * (p as f32 + 0.5) / n_phi as f32;
+                    sum += f(wo, &Vec3::new(sin_i * phi.cos(), sin_i * phi.sin(), cos_i));
+                }
+            }
+            sum / (n_theta * n_phi) as f32
+        }
+    }
+
+    fn average(&self, roughness: f32) -> f32 {
+        let y = roughness.clamp(0.0, 1.0) * (AlbedoTable::SIZE - 1) as f32;
+        let j = (y as usize).min(AlbedoTable::SIZE - 2);
+        self.averages[j] + (self.averages[j + 1] - self.averages[j]) * (y - j as f32)
+    }
+
+    // Kulla and Conty's lobe for light bouncing more than once between microfacets, which the single
+    // scattering albedo leaves out. It reflects exactly 1 - E(cos_o), shaped like a diffuse lobe, and
+    // is given here times the cosine with `cos_i` on either side of the surface.
+    fn multiple_scattering(&self, cos_o: f32, cos_i: f32, roughness: f32) -> f32 {
+        let missing = 1.0 - self.average(roughness);
+        if missing < 1e-4 {
+            return 0.0;
+        }
+        (1.0 - self.lookup(cos_o, roughness)) * (1.0 - self.lookup(cos_i.abs(), roughness)) * cos_i.abs() / (PI * missing)
+    }
+
+    fn lookup(&self, cos_theta: f32, roughness: f32) -> f32 {
+        let last = AlbedoTable::SIZE - 1;
+        let x = cos_theta.clamp(0.0, 1.0) * last as f32;
+        let y = roughness.clamp(0.0, 1.0) * last as f32;
+        let (i, j) = ((x as usize).min(last - 1), (y as usize).min(last - 1));
+        let (fx, fy) = (x - i as f32, y - j as f32);
+
+        let row = |i: usize| self.values[i][j] + (self.values[i][j + 1] - self.values[i][j]) * fy;
+        row(i) + (row(i + 1) - row(i)) * fx
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let (relative_ior, interfaces) = self.interfaces(ray_in);
+        let lobes = self.lobes(ray_in, rec, &wo, relative_ior, &interfaces);
+
+        // Pick a lobe, then weigh the direction by all of them
+        let mut u = rand.random_float();
+        let lobe = lobes.probabilities.iter().position(|&p| {
+            u -= p;
+            u < 0.0
+        }).unwrap_or(4);
+
+        let wi = match lobe {
+            1 | 2 if rand.random_float() < lobes.reflection_missing => rand.random_cosine_direction(),
+            4 if rand.random_float() < lobes.interface_missing => {
+                let wi = rand.random_cosine_direction();
+                if rand.random_float() < lobes.interface.fresnel { wi } else { Vec3::new(wi.x, wi.y, -wi.z) }
+            }
+            0..=2 => {
+                let ggx = if lobe == 0 { lobes.coat_ggx } else { lobes.ggx };
+                let wm = ggx.sample_visible_normal(&wo, rand.random_float(), rand.random_float());
+                Vec3::reflect(&(wo * -1.0), &wm)
+            }
+            3 => rand.random_cosine_direction(),
+            _ => lobes.ggx.sample_dielectric(&wo, lobes.eta, rand.random_float(), rand.random_float(), rand.random_float())?.0,
+        };
+
+        let direction = frame.to_world(&wi);
+        if wi.z == 0.0 || (Vec3::dot(&direction, &rec.normal) > 0.0) != (wi.z > 0.0) {
+            return None;
+        }
+
+        let (value, pdf) = Principled::eval_local(&lobes, &wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some((Ray::new(rec.point, direction), value / pdf))
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        let wi = frame.to_local(&direction.unit_vector());
+        if wo.z <= 0.0 || wi.z == 0.0 || (Vec3::dot(direction, &rec.normal) > 0.0) != (wi.z > 0.0) {
+            return None;
+        }
+
+        let (relative_ior, interfaces) = self.interfaces(ray_in);
+        let (value, pdf) = Principled::eval_local(&self.lobes(ray_in, rec, &wo, relative_ior, &interfaces), &wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((value, pdf))
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.params.thin_film.is_some() && ray_in.wavelengths.is_some()
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        Box::new(Principled {
+            params: self.params.clone(),
+            specular_albedo: Arc::clone(&self.specular_albedo),
+            interfaces: Arc::clone(&self.interfaces),
+        })
+    }
+}
+
+
+// ----------------------------------------------
+// Clearcoat -----------------------------------
+// ----------------------------------------------
+
+// Varnish, lacquer and car paint: a rough dielectric coat over any other material. The coat reflects
+// its Fresnel share of the light and hands the rest to the base, optionally tinted on the way through.
+#[derive(Debug)]
+pub struct Clearcoat {
+    base: Box<dyn Material>,
+    roughness: Arc<dyn Texture>,
+    ior: f32,
+    absorption: Option<Color>, // Color of light crossing the coat once, straight on
+    albedo: Arc<AlbedoTable>,
+}
+
+impl Clearcoat {
+    pub fn new(base: Box<dyn Material>, roughness: f32, ior: f32) -> Box<Self> {
+        Clearcoat::textured(base, SolidColor::scalar(roughness), ior)
+    }
+
+    pub fn textured(base: Box<dyn Material>, roughness: Arc<dyn Texture>, ior: f32) -> Box<Self> {
+        let albedo = AlbedoTable::dielectric(ior);
+        Box::new(Clearcoat { base, roughness, ior, absorption: None, albedo })
+    }
+
+    pub fn with_absorption(mut self: Box<Self>, color: Color) -> Box<Self> {
+        self.absorption = Some(color);
+        self
+    }
+
+    // Light reaching the base through the coat and leaving along `wi`, travelling further the more
+    // slanted the refracted directions are. Given at the ray's wavelengths when the base answers spectrally.
+    fn transmittance(&self, ray_in: &Ray, wo: &Vec3, wi: &Vec3) -> Color {
+        let Some(color) = self.absorption else { return Color::new(1.0, 1.0, 1.0) };
+
+        let refracted_cos = |cos: f32| (1.0 - (1.0 - cos * cos) / (self.ior * self.ior)).max(1e-4).sqrt();
+        let length = 1.0 / refracted_cos(wo.z) + 1.0 / refracted_cos(wi.z.abs());
+        let color = Color::new(color.x.max(1e-6).powf(length), color.y.max(1e-6).powf(length), color.z.max(1e-6).powf(length));
+        if self.base.spectral(ray_in) { upsampled(ray_in, &color) } else { color }
+    }
+}
+
+impl Material for Clearcoat {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        // The coat is only on the outside
+        if !rec.front_face {
+            return self.base.scatter(ray_in, rec, rand);
+        }
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let ggx = Ggx::from_roughness(rec.lookup(&self.roughness).x, 0.0);
+        let coat = self.albedo.lookup(wo.z, rec.lookup(&self.roughness).x);
+
+        // Reflect off the coat as often as it reflects on average, which leaves F * G2 / G1 over that share
+        if rand.random_float() < coat {
+            let wm = ggx.sample_visible_normal(&wo, rand.random_float(), rand.random_float());
+            let wi = Vec3::reflect(&(wo * -1.0), &wm);
+            let direction = frame.to_world(&wi);
+            if wi.z <= 0.0 || Vec3::dot(&direction, &rec.normal) <= 0.0 {
+                return None;
+            }
+
+            let weight = fresnel_dielectric(Vec3::dot(&wo, &wm), self.ior) * ggx.g2(&wo, &wi) / ggx.g1(&wo) / coat;
+            return Some((Ray::new(rec.point, direction), Color::new(weight, weight, weight)));
+        }
+
+        // `eval` only covers the coat over a specular base, so light sampling mustn't be weighed against it
+        let (mut scattered, attenuation) = self.base.scatter(ray_in, rec, rand)?;
+        if self.base.eval(ray_in, rec, scattered.direction()).is_none() {
+            scattered.specular = true;
+        }
+        let wi = frame.to_local(&scattered.direction().unit_vector());
+        Some((scattered, &attenuation * &self.transmittance(ray_in, &wo, &wi)))
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        if !rec.front_face {
+            return self.base.eval(ray_in, rec, direction);
+        }
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(ray_in.direction().unit_vector() * -1.0));
+        let wi = frame.to_local(&direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let roughness = rec.lookup(&self.roughness).x;
+        let coat = self.albedo.lookup(wo.z, roughness);
+
+        let mut result = None;
+
+        // A specular base adds nothing here, and its samples are flagged in `scatter`
+        if let Some((base_value, base_pdf)) = self.base.eval(ray_in, rec, direction) {
+            result = Some((&base_value * &self.transmittance(ray_in, &wo, &wi) * (1.0 - coat), base_pdf * (1.0 - coat)));
+        }
+
+        if Vec3::dot(direction, &rec.normal) > 0.0
+            && let Some((reflection, coat_pdf, wm)) = Ggx::from_roughness(roughness, 0.0).eval_reflection(&wo, &wi)
+        {
+            let fresnel = fresnel_dielectric(Vec3::dot(&wo, &wm), self.ior);
+            let (value, pdf) = result.unwrap_or((Color::new(0.0, 0.0, 0.0), 0.0));
+            result = Some((value + Color::new(1.0, 1.0, 1.0) * (fresnel * reflection), pdf + coat * coat_pdf));
+        }
+
+        result
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f32 {
+        self.base.alpha(rec)
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.base.spectral(ray_in)
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        Box::new(Clearcoat {
+            base: self.base.clone(),
+            roughness: Arc::clone(&self.roughness),
+            ior: self.ior,
+            absorption: self.absorption,
+            albedo: Arc::clone(&self.albedo),
+        })
+    }
+}
+
+
+// ----------------------------------------------
+// Subsurface -----------------------------------
+// ----------------------------------------------
+
+// Skin, wax, marble and milk. Light refracts into the closed object the material is attached to and
+// random-walks through it, scattering isotropically every `mean_free_path` on average, until it finds
+// its way out again. Red light usually travels the furthest, so each walk follows a single wavelength
+// and the mean free path and albedo are up-sampled to it, like dispersion does for the index.
+//
+// The whole walk runs inside `scatter`, traced against `shape`, a copy of the object's geometry whose
+// own material is never used. It returns the last segment up to the point the walk leaves through, where
+// the exit is treated as a diffuse transmission so that lights can be sampled from it.
+#[derive(Clone)]
+pub struct Subsurface {
+    shape: Arc<dyn Hittable>,
+    boundary: Dielectric,
+    albedo: Color, // Chance of light scattering rather than being absorbed at each event
+    mean_free_path: Color,
+}
+
+impl Debug for Subsurface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subsurface")
+            .field("boundary", &self.boundary)
+            .field("albedo", &self.albedo)
+            .field("mean_free_path", &self.mean_free_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Subsurface {
+    // Walks giving up after this many events are absorbed, they barely contribute by then
+    const MAX_EVENTS: u32 = 1024;
+
+    pub fn new(shape: Arc<dyn Hittable>, albedo: Color, mean_free_path: Color, refractive_index: f32) -> Box<Self> {
+        let boundary = *Dielectric::new(Color::new(1.0, 1.0, 1.0), refractive_index);
+        Box::new(Subsurface { shape, boundary, albedo, mean_free_path })
+    }
+
+    // Events per unit of distance at the walk's wavelength
+    fn extinction(&self, lambda: f32) -> f32 {
+        1.0 / upsample_rgb(&self.mean_free_path, lambda).max(1e-4)
+    }
+
+    // Transmission out of the object for light arriving at the boundary from the inside
+    fn exit_probability(&self, ray_in: &Ray, rec: &HitRecord) -> f32 {
+        let cos_theta = Vec3::dot(&(ray_in.direction().unit_vector() * -1.0), &rec.shading_normal).min(1.0);
+        1.0 - fresnel_dielectric(cos_theta, 1.0 / self.boundary.refractive_index)
+    }
+
+    // Follows light that refracted in until it reaches a point of the boundary it leaves through.
+    // Returns the segment ending there, with the albedo of every event along the way.
+    fn walk(&self, mut ray: Ray, lambda: f32, rand: &mut RandomGenerator) -> Option<(Ray, Color)> {
+        let extinction = self.extinction(lambda);
+        let albedo = upsample_rgb(&self.albedo, lambda).clamp(0.0, 1.0);
+        let media = ray.media;
+        let mut throughput = 1.0;
+
+        for _ in 0..Self::MAX_EVENTS {
+            let mut rec = HitRecord::new();
+            if !self.shape.hit(&ray, Interval::new(0.001, f32::MAX), &mut rec, rand) {
+                return None; // Lost through a shape that isn't closed, or right next to it
+            }
+
+            let length = ray.direction().length();
+            let distance = -(1.0 - rand.random_float()).ln() / extinction;
+            if distance < rec.t * length {
+                throughput *= albedo;
+                ray = Ray::new(ray.at(distance / length), rand.random_unit_vector_on_sphere());
+            } else if self.exit_probability(&ray, &rec) > rand.random_float() {
+                ray.wavelength = Some(lambda);
+                ray.media = media;
+                return Some((ray, Color::new(throughput, throughput, throughput)));
+            } else {
+                let direction = Vec3::reflect(&ray.direction().unit_vector(), &rec.shading_normal);
+                ray = Ray::new(rec.point, direction);
+            }
+        }
+        None
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let lambda = ray_in.wavelength
+            .or(ray_in.wavelengths.map(|w| w.hero()))
+            .unwrap_or_else(|| sample_wavelength(rand.random_float()));
+
+        if rec.front_face {
+            let (mut scattered, attenuation) = self.boundary.scatter(ray_in, rec, rand)?;
+            if Vec3::dot(scattered.direction(), &rec.normal) >= 0.0 {
+                return Some((scattered, attenuation));
+            }
+            scattered.media = ray_in.media; // The walk leaves through a different lobe, so it isn't tracked as a medium
+            return self.walk(scattered, lambda, rand);
+        }
+
+        // The walk already chose to leave here
+        let direction = Onb::new(&(rec.shading_normal * -1.0)).to_world(&rand.random_cosine_direction());
+        let mut scattered = Ray::new(rec.point, direction);
+        scattered.wavelength = Some(lambda);
+        scattered.media = ray_in.media;
+        Some((scattered, Color::new(1.0, 1.0, 1.0)))
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        ray_in.wavelength?;
+        if rec.front_face {
+            return None;
+        }
+
+        // Lights are only seen through the exit lobe
+        let cos_theta = -Vec3::dot(&direction.unit_vector(), &rec.shading_normal);
+        if cos_theta <= 0.0 || Vec3::dot(direction, &rec.normal) >= 0.0 {
+            return None;
+        }
+
+        let pdf = cos_theta / PI;
+        Some((Color::new(pdf, pdf, pdf), pdf))
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+
+// ----------------------------------------------
+// Participating media --------------------------
+// ----------------------------------------------
+
+// How particles in a medium scatter light, following Henyey and Greenstein. Positive `g` scatters
+// mostly forwards like haze and clouds, negative mostly backwards, and 0 evenly in all directions.
+#[derive(Clone, Debug)]
+pub struct Phase {
+    albedo: Arc<dyn Texture>,
+    g: f32,
+    emission: Color, // Glow of hot particles, like those in fire
+}
+
+impl Phase {
+    pub fn isotropic(color: Color) -> Box<Self> {
+        Phase::henyey_greenstein(color, 0.0)
+    }
+
+    pub fn henyey_greenstein(color: Color, g: f32) -> Box<Self> {
+        Box::new(Phase { albedo: SolidColor::new(color), g: g.clamp(-0.99, 0.99), emission: Color::new(0.0, 0.0, 0.0) })
+    }
+
+    // The same phase function for particles that also glow, which volumes hand out per collision
+    pub fn emitting(&self, emission: Color) -> Box<Self> {
+        Box::new(Phase { albedo: Arc::clone(&self.albedo), g: self.g, emission })
+    }
+
+    // Density over directions, for the cosine of the angle between the ray and the scattered direction
+    fn pdf(&self, cos_theta: f32) -> f32 {
+        let denom = 1.0 + self.g * self.g - 2.0 * self.g * cos_theta;
+        (1.0 - self.g * self.g) / (4.0 * PI * denom * denom.max(1e-6).sqrt())
+    }
+}
+
+impl Material for Phase {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let g = self.g;
+        let u = rand.random_float();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rand.random_float();
+        let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let direction = Onb::new(&ray_in.direction().unit_vector()).to_world(&local);
+
+        // Sampling follows the phase function exactly, leaving only the albedo
+        Some((Ray::new(rec.point, direction), rec.lookup(&self.albedo)))
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let pdf = self.pdf(Vec3::dot(&ray_in.direction().unit_vector(), &direction.unit_vector()));
+        Some((rec.lookup(&self.albedo) * pdf, pdf))
+    }
+
+    // Glowing particles are the ones absorbing light, so the glow is weighted by the chance a collision absorbs
+    fn emitted(&self, _ray_in: &Ray, rec: &HitRecord) -> Color {
+        &self.emission * &(Color::new(1.0, 1.0, 1.0) - rec.lookup(&self.albedo))
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        self.emitting(self.emission)
+    }
+}
+
+
+// ----------------------------------------------
+// Normal and bump mapping ----------------------
+// ----------------------------------------------
+
+// Tangent space normal map over any material. The map stores (x, y, z) remapped to [0, 1],
+// with x along dpdu, y along dpdv and z along the surface normal, so it should be loaded without sRGB decoding.
+#[derive(Debug)]
+pub struct NormalMap {
+    base: Box<dyn Material>,
+    map: Arc<dyn Texture>,
+    strength: f32, // Scales the tangent components, 0 leaves the surface flat
+}
+
+impl NormalMap {
+    pub fn new(base: Box<dyn Material>, map: Arc<dyn Texture>, strength: f32) -> Box<Self> {
+        Box::new(NormalMap { base, map, strength })
+    }
+
+    fn perturb(&self, ray_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let encoded = rec.lookup(&self.map);
+        let x = (2.0 * encoded.x - 1.0) * self.strength;
+        let y = (2.0 * encoded.y - 1.0) * self.strength;
+        let z = (2.0 * encoded.z - 1.0).max(0.0);
+
+        let mut shading = rec.clone();
+        shading.set_shading_normal(ray_in, &rec.shading_frame().to_world(&Vec3::new(x, y, z)));
+        shading
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        self.base.scatter(ray_in, &self.perturb(ray_in, rec), rand)
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        self.base.eval(ray_in, &self.perturb(ray_in, rec), direction)
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f32 {
+        self.base.alpha(rec)
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.base.spectral(ray_in)
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        NormalMap::new(self.base.clone(), Arc::clone(&self.map), self.strength)
+    }
+}
+
+// Bump map over any material, displacing the surface along its normal by a height texture (red channel)
+#[derive(Debug)]
+pub struct BumpMap {
+    base: Box<dyn Material>,
+    height: Arc<dyn Texture>,
+    scale: f32, // Height of a texture value of 1 in world units
+}
+
+impl BumpMap {
+    pub fn new(base: Box<dyn Material>, height: Arc<dyn Texture>, scale: f32) -> Box<Self> {
+        Box::new(BumpMap { base, height, scale })
+    }
+
+    fn perturb(&self, ray_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let d = rec.uv_derivatives;
+
+        // Finite difference steps follow the pixel footprint, with a small fallback without differentials
+        let mut du = 0.5 * (d.dudx.abs() + d.dudy.abs());
+        let mut dv = 0.5 * (d.dvdx.abs() + d.dvdy.abs());
+        if du == 0.0 { du = 0.0005; }
+        if dv == 0.0 { dv = 0.0005; }
+
+        let height_at = |u: f32, v: f32, point: &Vec3| self.height.value(u, v, point).x * self.scale;
+
+        let base_height = height_at(rec.u, rec.v, &rec.point);
+        let height_u = height_at(rec.u + du, rec.v, &(rec.point + rec.dpdu * du));
+        let height_v = height_at(rec.u, rec.v + dv, &(rec.point + rec.dpdv * dv));
+
+        let n = rec.shading_normal;
+        let dpdu = rec.dpdu + n * ((height_u - base_height) / du);
+        let dpdv = rec.dpdv + n * ((height_v - base_height) / dv);
+
+        let mut shading = rec.clone();
+        shading.set_shading_normal(ray_in, &Vec3::cross(&dpdu, &dpdv));
+        shading
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        self.base.scatter(ray_in, &self.perturb(ray_in, rec), rand)
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        self.base.eval(ray_in, &self.perturb(ray_in, rec), direction)
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f32 {
+        self.base.alpha(rec)
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.base.spectral(ray_in)
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        BumpMap::new(self.base.clone(), Arc::clone(&self.height), self.scale)
+    }
+}
+
+
+// ----------------------------------------------
+// Mixing and alpha masks -----------------------
+// ----------------------------------------------
+
+// Blends two materials by a weight, 0 giving the first and 1 the second. Each scatter picks one of them at
+// random, which varies a surface like rust patches on steel without splitting it into separate objects.
+#[derive(Debug)]
+pub struct MixMaterial {
+    first: Box<dyn Material>,
+    second: Box<dyn Material>,
+    weight: Arc<dyn Texture>, // Red channel
+}
+
+impl MixMaterial {
+    pub fn new(first: Box<dyn Material>, second: Box<dyn Material>, weight: f32) -> Box<Self> {
+        MixMaterial::textured(first, second, SolidColor::scalar(weight))
+    }
+
+    pub fn textured(first: Box<dyn Material>, second: Box<dyn Material>, weight: Arc<dyn Texture>) -> Box<Self> {
+        Box::new(MixMaterial { first, second, weight })
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f32 {
+        rec.lookup(&self.weight).x.clamp(0.0, 1.0)
+    }
+
+    // When only one side answers spectrally, the other one's RGB is up-sampled to match
+    fn convert(&self, side: &dyn Material, ray_in: &Ray, value: Color) -> Color {
+        if self.spectral(ray_in) && !side.spectral(ray_in) { upsampled(ray_in, &value) } else { value }
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        let side = if rand.random_float() < self.weight(rec) { &self.second } else { &self.first };
+        let (mut scattered, attenuation) = side.scatter(ray_in, rec, rand)?;
+
+        // The blend's pdf only covers the sides that can be evaluated, so a sample from a specular side
+        // mustn't be weighed against light sampling with it
+        if side.eval(ray_in, rec, scattered.direction()).is_none() {
+            scattered.specular = true;
+        }
+        Some((scattered, self.convert(side.as_ref(), ray_in, attenuation)))
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        let weight = self.weight(rec);
+        let sides = [(self.first.as_ref(), 1.0 - weight), (self.second.as_ref(), weight)];
+
+        // Each side is picked with its weight, so both the value and the pdf are weighted sums. A specular
+        // side adds nothing, and only if neither side can be evaluated is the blend specular.
+        let mut result = None;
+        for (side, weight) in sides {
+            if weight <= 0.0 {
+                continue;
+            }
+            if let Some((value, pdf)) = side.eval(ray_in, rec, direction) {
+                let (sum, pdf_sum) = result.unwrap_or((Color::new(0.0, 0.0, 0.0), 0.0));
+                result = Some((sum + self.convert(side, ray_in, value) * weight, pdf_sum + pdf * weight));
+            }
+        }
+        result
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Color {
+        let weight = self.weight(rec);
+        self.first.emitted(ray_in, rec) * (1.0 - weight) + self.second.emitted(ray_in, rec) * weight
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f32 {
+        let weight = self.weight(rec);
+        self.first.alpha(rec) * (1.0 - weight) + self.second.alpha(rec) * weight
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.first.spectral(ray_in) || self.second.spectral(ray_in)
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        MixMaterial::textured(self.first.clone(), self.second.clone(), Arc::clone(&self.weight))
+    }
+}
+
+// Cuts holes into any material for leaves, fences and lace. Where the mask (red channel) is 0 the surface
+// isn't there at all, so camera and shadow rays carry on to whatever is behind it.
+#[derive(Debug)]
+pub struct AlphaMask {
+    base: Box<dyn Material>,
+    mask: Arc<dyn Texture>,
+}
+
+impl AlphaMask {
+    pub fn new(base: Box<dyn Material>, mask: Arc<dyn Texture>) -> Box<Self> {
+        Box::new(AlphaMask { base, mask })
+    }
+}
+
+impl Material for AlphaMask {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        self.base.scatter(ray_in, rec, rand)
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        self.base.eval(ray_in, rec, direction)
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Color {
+        self.base.emitted(ray_in, rec)
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f32 {
+        rec.lookup(&self.mask).x.clamp(0.0, 1.0) * self.base.alpha(rec)
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.base.spectral(ray_in)
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        AlphaMask::new(self.base.clone(), Arc::clone(&self.mask))
+    }
+}
+
+
+// ----------------------------------------------
+// Material library -----------------------------
+// ----------------------------------------------
+
+// Named materials shared between objects, for scene code building many objects out of a few materials.
+// Objects get a handle to the one stored material, so a scene with thousands of objects in a handful of
+// materials only keeps that handful. Each hit still boxes a new handle, but that copies a pointer rather
+// than the material with its textures and tables. A shared dielectric is a single medium, which is only
+// right for objects that don't overlap.
+//
+// Scenes are built in code: there is no scene file loader, OBJ/MTL importer or command line override
+// looking materials up by name.
+pub struct MaterialLibrary {
+    materials: HashMap<String, Arc<dyn Material>>,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> Self {
+        MaterialLibrary { materials: HashMap::new() }
+    }
+
+    // Replaces any material already stored under the name
+    pub fn add(&mut self, name: &str, material: Box<dyn Material>) {
+        self.materials.insert(name.to_string(), Arc::from(material));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Box<dyn Material>> {
+        let material = self.materials.get(name)?;
+        Some(Box::new(Shared(Arc::clone(material))))
+    }
+}
+
+// Handle to a material in a library. It forwards every method of `Material` by hand, so methods added
+// to the trait with a default need forwarding here too.
+#[derive(Debug)]
+struct Shared(Arc<dyn Material>);
+
+impl Material for Shared {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rand: &mut RandomGenerator) -> Option<(Ray, Vec3)> {
+        self.0.scatter(ray_in, rec, rand)
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Color, f32)> {
+        self.0.eval(ray_in, rec, direction)
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Color {
+        self.0.emitted(ray_in, rec)
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f32 {
+        self.0.alpha(rec)
+    }
+
+    fn spectral(&self, ray_in: &Ray) -> bool {
+        self.0.spectral(ray_in)
+    }
+
+    fn clone(&self) -> Box<dyn Material> {
+        Box::new(Shared(Arc::clone(&self.0)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // White furnace: a white surface under uniform light should scatter all of it, counting energy
+    // rather than radiance for rays transmitted into the object
+    fn furnace(params: PrincipledParams, cos_o: f32) -> f32 {
+        let ior = params.ior;
+        let material = Principled::new(params);
+
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.shading_normal = rec.normal;
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_face = true;
+
+        let sin_o = (1.0 - cos_o * cos_o).sqrt();
+        let ray = Ray::new(Vec3::new(sin_o, 0.0, cos_o), Vec3::new(-sin_o, 0.0, -cos_o));
+        let mut rand = RandomGenerator::seeded(7);
+
+        let n = 10_000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some((scattered, attenuation)) = material.scatter(&ray, &rec, &mut rand) {
+                let scale = if scattered.direction().z < 0.0 { ior * ior } else { 1.0 };
+                sum += attenuation.x * scale;
+            }
+        }
+        sum / n as f32
+    }
+
+    // The coat over a mirror can still be lit by light sampling, while the mirror's own samples are
+    // flagged so they aren't weighed against it
+    #[test]
+    fn clearcoat_over_specular_base() {
+        let material = Clearcoat::new(Metal::new(Color::new(0.9, 0.9, 0.9), 0.0), 0.3, 1.5);
+
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.shading_normal = rec.normal;
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_face = true;
+
+        let ray = Ray::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0));
+        let (value, pdf) = material.eval(&ray, &rec, &Vec3::new(1.0, 0.2, 1.2)).expect("coat lobe");
+        assert!(value.x > 0.0 && pdf > 0.0);
+
+        let mut rand = RandomGenerator::seeded(3);
+        let (mut flagged, mut coat) = (0, 0);
+        for _ in 0..1000 {
+            let Some((scattered, _)) = material.scatter(&ray, &rec, &mut rand) else { continue };
+            if scattered.specular {
+                let mirror = Vec3::new(1.0, 0.0, 1.0).unit_vector();
+                assert!(Vec3::dot(&scattered.direction().unit_vector(), &mirror) > 0.9999);
+                flagged += 1;
+            } else {
+                assert!(material.eval(&ray, &rec, scattered.direction()).is_some());
+                coat += 1;
+            }
+        }
+        assert!(flagged > 0 && coat > 0);
+    }
+
+    #[test]
+    fn principled_shares_tables_per_ior() {
+        let glass = || Principled::new(PrincipledParams { ior: 1.45, ..Default::default() });
+        let (a, b) = (glass(), glass());
+        assert!(Arc::ptr_eq(&a.specular_albedo, &b.specular_albedo));
+        assert!(Arc::ptr_eq(&a.interfaces, &b.interfaces));
+
+        let diamond = Principled::new(PrincipledParams { ior: 2.42, ..Default::default() });
+        assert!(!Arc::ptr_eq(&a.specular_albedo, &diamond.specular_albedo));
+    }
+
+    #[test]
+    fn principled_conserves_energy() {
+        let white = || SolidColor::scalar(1.0);
+        for roughness in [0.2, 0.5, 0.8, 1.0] {
+            for cos_o in [0.2, 0.5, 0.8, 1.0] {
+                let metal = PrincipledParams { base_color: white(), metallic: white(), roughness: SolidColor::scalar(roughness), ..Default::default() };
+                let plastic = PrincipledParams { base_color: white(), roughness: SolidColor::scalar(roughness), ..Default::default() };
+                let glass = PrincipledParams { base_color: white(), transmission: white(), roughness: SolidColor::scalar(roughness), ..Default::default() };
+
+                for (name, params) in [("metal", metal), ("plastic", plastic), ("glass", glass)] {
+                    let albedo = furnace(params, cos_o);
+                    assert!((albedo - 1.0).abs() < 0.03, "{name} at roughness {roughness}, cos {cos_o}: {albedo}");
+                }
+            }
+        }
+    }
+
+    // Without absorption walks leave the object again, through a segment ending on its surface
+    #[test]
+    fn subsurface_walks_return_at_the_exit() {
+        use crate::hittable::Sphere;
+
+        let shape = || Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+        let white = Color::new(1.0, 1.0, 1.0);
+        let material = Subsurface::new(shape(), white, white * 0.2, 1.3);
+        let sphere = shape();
+
+        let ray = Ray::new(Vec3::new(0.3, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(sphere.hit(&ray, Interval::new(0.001, f32::MAX), &mut rec, &mut RandomGenerator::seeded(1)));
+
+        let mut rand = RandomGenerator::seeded(5);
+        let (mut walks, mut exits) = (0, 0);
+        for _ in 0..1000 {
+            // Only events within the hit epsilon of the surface lose a walk
+            let Some((scattered, attenuation)) = material.scatter(&ray, &rec, &mut rand) else { continue };
+            if (*scattered.origin() - rec.point).length() < 1e-6 && Vec3::dot(scattered.direction(), &rec.normal) >= 0.0 {
+                continue; // Reflected off the boundary
+            }
+            walks += 1;
+            assert!((attenuation.x - 1.0).abs() < 1e-6);
+
+            let mut exit = HitRecord::new();
+            assert!(sphere.hit(&scattered, Interval::new(0.001, f32::MAX), &mut exit, &mut rand));
+            assert!(!exit.front_face);
+            let (leaving, _) = material.scatter(&scattered, &exit, &mut rand).expect("exit");
+            if Vec3::dot(leaving.direction(), &exit.point) > 0.0 {
+                exits += 1;
+            }
+        }
+        assert!(walks > 850 && exits == walks);
+    }
+
+    // Rough glass in a liquid of the same index doesn't bend light at all
+    #[test]
+    fn rough_dielectric_takes_index_from_medium() {
+        use crate::util::MediumStack;
+
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.shading_normal = rec.normal;
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_face = true;
+
+        let mut media = MediumStack::default();
+        media.push(Medium { id: 1, priority: 0, refractive_index: 1.33, absorption: Vec3::new(0.0, 0.0, 0.0) });
+        let mut ray = Ray::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0));
+        ray.media = Some(media);
+
+        let material = RoughDielectric::new(1.33, 0.5);
+        let mut rand = RandomGenerator::seeded(11);
+        for _ in 0..100 {
+            let (scattered, _) = material.scatter(&ray, &rec, &mut rand).expect("transmission");
+            let straight = Vec3::dot(&scattered.direction().unit_vector(), &ray.direction().unit_vector());
+            assert!(straight > 0.9999, "{straight}");
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::f32::consts::PI;
+
+use crate::util::Vec3;
+
+use Vec3 as Color;
+
+// Anisotropic Trowbridge-Reitz (GGX) microfacet distribution. Directions are in the local
+// shading frame: x along the tangent, y along the bitangent and z along the normal.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    // Perceptual roughness in [0, 1] is squared into alpha. Anisotropy in [0, 1) stretches the
+    // highlight along the tangent (Burley's remapping).
+    pub fn from_roughness(roughness: f32, anisotropy: f32) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        Ggx {
+            alpha_x: (alpha / aspect).max(1e-3),
+            alpha_y: (alpha * aspect).max(1e-3),
+        }
+    }
+
+    // Density of microfacet normals
+    pub fn d(&self, m: &Vec3) -> f32 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    // Smith auxiliary function
+    fn lambda(&self, w: &Vec3) -> f32 {
+        if w.z == 0.0 {
+            return f32::MAX;
+        }
+        let tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        0.5 * (-1.0 + (1.0 + tan2).sqrt())
+    }
+
+    // Smith masking for one direction
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height correlated Smith masking-shadowing for a pair of directions
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of normals visible from `wo`, which is what `sample_visible_normal` draws from
+    pub fn visible_d(&self, wo: &Vec3, m: &Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Vec3::dot(wo, m).max(0.0) * self.d(m) / wo.z
+    }
+
+    // Samples a microfacet normal visible from `wo` (Heitz 2018, "Sampling the GGX Distribution of Visible Normals")
+    pub fn sample_visible_normal(&self, wo: &Vec3, u1: f32, u2: f32) -> Vec3 {
+        // Stretch the view direction to the hemisphere configuration
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0.0 { Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t2 = Vec3::cross(&vh, &t1);
+
+        // Sample the projected area of the visible hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // Unstretch back to the ellipsoid configuration
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+
+    // Reflection off the microfacets without the Fresnel term, as BSDF * cos and the pdf of sampling `wi`
+    // by reflecting `wo` off a visible normal. Also returns the half vector to evaluate Fresnel at.
+    pub fn eval_reflection(&self, wo: &Vec3, wi: &Vec3) -> Option<(f32, f32, Vec3)> {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return None;
+        }
+
+        let wm = (*wo + *wi).unit_vector();
+        let value = self.d(&wm) * self.g2(wo, wi) / (4.0 * wo.z);
+        let pdf = self.visible_d(wo, &wm) / (4.0 * Vec3::dot(wo, &wm));
+        Some((value, pdf, wm))
+    }
+
+    // Samples reflection or transmission through a rough dielectric interface (Walter et al. 2007), where
+    // `eta` is the index below the surface over the index above it. Picking between the two by the Fresnel
+    // term of the sampled microfacet leaves a weight of G2 / G1, with transmitted radiance scaled by 1 / eta^2.
+    pub fn sample_dielectric(&self, wo: &Vec3, eta: f32, u1: f32, u2: f32, u3: f32) -> Option<(Vec3, f32)> {
+        let wm = self.sample_visible_normal(wo, u1, u2);
+        let fresnel = fresnel_dielectric(Vec3::dot(wo, &wm), eta);
+
+        match refract(wo, &wm, eta) {
+            Some(wt) if u3 >= fresnel => {
+                if wt.z >= 0.0 {
+                    return None;
+                }
+                // Radiance is compressed into a smaller solid angle when entering a denser medium
+                Some((wt, self.g2(wo, &wt) / self.g1(wo) / (eta * eta)))
+            }
+            _ => {
+                let wr = Vec3::reflect(&(*wo * -1.0), &wm);
+                if wr.z <= 0.0 {
+                    return None;
+                }
+                Some((wr, self.g2(wo, &wr) / self.g1(wo)))
+            }
+        }
+    }
+
+    // BSDF * cos and pdf of `sample_dielectric`, for `wi` on either side of the surface
+    pub fn eval_dielectric(&self, wo: &Vec3, wi: &Vec3, eta: f32) -> Option<(f32, f32)> {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return None;
+        }
+
+        let reflect = wi.z > 0.0;
+        let etap = if reflect { 1.0 } else { eta };
+
+        // Generalized half vector, facing the same way as the normal
+        let mut wm = (*wi * etap + *wo).unit_vector();
+        if wm.z < 0.0 {
+            wm = wm * -1.0;
+        }
+        if Vec3::dot(&wm, wi) * wi.z < 0.0 || Vec3::dot(&wm, wo) <= 0.0 {
+            return None; // Backfacing microfacet
+        }
+
+        let fresnel = fresnel_dielectric(Vec3::dot(wo, &wm), eta);
+        let visible = self.visible_d(wo, &wm);
+
+        if reflect {
+            let value = self.d(&wm) * self.g2(wo, wi) * fresnel / (4.0 * wo.z);
+            return Some((value, visible / (4.0 * Vec3::dot(wo, &wm)) * fresnel));
+        }
+
+        let denom = (Vec3::dot(wi, &wm) + Vec3::dot(wo, &wm) / etap).powi(2);
+        let value = self.d(&wm) * (1.0 - fresnel) * self.g2(wo, wi)
+            * (Vec3::dot(wi, &wm) * Vec3::dot(wo, &wm) / (denom * wo.z)).abs()
+            / (etap * etap);
+        Some((value, visible * Vec3::dot(wi, &wm).abs() / denom * (1.0 - fresnel)))
+    }
+}
+
+// Fresnel reflectance of a dielectric interface for unpolarized light. `eta` is the index on the far side
+// over the index on the near side, and a negative cosine means the light arrives from the far side.
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_theta_i < 0.0 { (-cos_theta_i, 1.0 / eta) } else { (cos_theta_i, eta) };
+    let cos_i = cos_i.min(1.0);
+
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0; // Total internal reflection
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// Refracts `wo` (pointing away from the surface, on the same side as `n`) through an interface where
+// `eta` is the far index over the near index. Returns None on total internal reflection.
+pub fn refract(wo: &Vec3, n: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = Vec3::dot(wo, n);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    Some(*wo * (-1.0 / eta) + *n * (cos_i / eta - cos_t))
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction eta + i k, per color channel
+pub fn fresnel_conductor(cos_theta_i: f32, eta: &Color, k: &Color) -> Color {
+    Color::new(
+        fresnel_complex(cos_theta_i, eta.x, k.x),
+        fresnel_complex(cos_theta_i, eta.y, k.y),
+        fresnel_complex(cos_theta_i, eta.z, k.z),
+    )
+}
+
+fn fresnel_complex(cos_theta_i: f32, eta: f32, k: f32) -> f32 {
+    let cos_i = cos_theta_i.clamp(0.0, 1.0);
+    let eta = Complex::new(eta, k);
+
+    // Snell's law with a complex index gives a complex transmitted cosine
+    let sin2_i = Complex::new(1.0 - cos_i * cos_i, 0.0);
+    let sin2_t = sin2_i / (eta * eta);
+    let cos_t = (Complex::new(1.0, 0.0) - sin2_t).sqrt();
+
+    let cos_i = Complex::new(cos_i, 0.0);
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+
+    0.5 * (r_parallel.norm() + r_perpendicular.norm())
+}
+
+// Reflectance of a film of index `film_ior`, `thickness` nanometres thick, on a substrate with complex index
+// eta + i k, lit from a medium of index `outside` at one wavelength. Light bouncing inside the film interferes
+// with itself (Airy summation), which is what colours soap bubbles and oil slicks. Averaged over polarizations.
+pub fn fresnel_thin_film(cos_theta_i: f32, outside: f32, film_ior: f32, thickness: f32, eta: f32, k: f32, lambda: f32) -> f32 {
+    let cos_i = cos_theta_i.clamp(0.0, 1.0);
+    let one = Complex::new(1.0, 0.0);
+    let n1 = Complex::new(outside, 0.0);
+    let n2 = Complex::new(film_ior, 0.0);
+    let n3 = Complex::new(eta, k);
+
+    // Snell's law keeps n sin(theta) the same in every layer
+    let sin2 = Complex::new(outside * outside * (1.0 - cos_i * cos_i), 0.0);
+    let cos_in = |n: Complex| (one - sin2 / (n * n)).sqrt();
+    let (c1, c2, c3) = (Complex::new(cos_i, 0.0), cos_in(n2), cos_in(n3));
+
+    let r_s = |na: Complex, ca: Complex, nb: Complex, cb: Complex| (na * ca - nb * cb) / (na * ca + nb * cb);
+    let r_p = |na: Complex, ca: Complex, nb: Complex, cb: Complex| (nb * ca - na * cb) / (nb * ca + na * cb);
+
+    // Phase picked up on a round trip through the film
+    let phase = (n2 * c2 * Complex::new(4.0 * PI * thickness / lambda, 0.0)).exp_i();
+    let airy = |r12: Complex, r23: Complex| {
+        let bounced = r23 * phase;
+        ((r12 + bounced) / (one + r12 * bounced)).norm()
+    };
+
+    let s = airy(r_s(n1, c1, n2, c2), r_s(n2, c2, n3, c3));
+    let p = airy(r_p(n1, c1, n2, c2), r_p(n2, c2, n3, c3));
+    (0.5 * (s + p)).clamp(0.0, 1.0)
+}
+
+// Complex index of refraction for a metal with the given reflectance at normal incidence and tint towards
+// grazing angles, following Gulbrandsen's "Artist Friendly Metallic Fresnel"
+pub fn conductor_from_reflectance(reflectance: &Color, edge_tint: &Color) -> (Color, Color) {
+    let fit = |r: f32, g: f32| {
+        let r = r.clamp(0.0, 0.99);
+        let sqrt_r = r.sqrt();
+        let n = g * (1.0 - r) / (1.0 + r) + (1.0 - g) * (1.0 + sqrt_r) / (1.0 - sqrt_r);
+        let k = ((r * (n + 1.0).powi(2) - (n - 1.0).powi(2)) / (1.0 - r)).max(0.0).sqrt();
+        (n, k)
+    };
+
+    let (x, y, z) = (fit(reflectance.x, edge_tint.x), fit(reflectance.y, edge_tint.y), fit(reflectance.z, edge_tint.z));
+    (Color::new(x.0, y.0, z.0), Color::new(x.1, y.1, z.1))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Complex { re, im }
+    }
+
+    // Squared magnitude
+    fn norm(&self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(&self) -> Complex {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+
+    // e^(i z), which decays rather than oscillates for the imaginary part of z
+    fn exp_i(&self) -> Complex {
+        let magnitude = (-self.im).exp();
+        Complex::new(magnitude * self.re.cos(), magnitude * self.re.sin())
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let scale = 1.0 / other.norm();
+        Complex::new(
+            scale * (self.re * other.re + self.im * other.im),
+            scale * (self.im * other.re - self.re * other.im),
+        )
+    }
+}
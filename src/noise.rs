@@ -0,0 +1,164 @@
+use crate::random::RandomGenerator;
+use crate::util::Vec3;
+
+use Vec3 as Point;
+
+const POINT_COUNT: usize = 256;
+
+// Gradient noise lattice, built from a RandomGenerator so it can be seeded
+#[derive(Debug)]
+pub struct Perlin {
+    rand_vec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rand: &mut RandomGenerator) -> Self {
+        let rand_vec = (0..POINT_COUNT).map(|_| rand.random_vec3_range(-1.0, 1.0).unit_vector()).collect();
+
+        Perlin {
+            rand_vec,
+            perm_x: Perlin::generate_perm(rand),
+            perm_y: Perlin::generate_perm(rand),
+            perm_z: Perlin::generate_perm(rand),
+        }
+    }
+
+    // Smooth noise in roughly [-1, 1]
+    pub fn noise(&self, p: &Point) -> f32 {
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
+
+        let i = p.x.floor() as i32;
+        let j = p.y.floor() as i32;
+        let k = p.z.floor() as i32;
+
+        let mut c = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.rand_vec[
+                        self.perm_x[((i + di as i32) & 255) as usize]
+                            ^ self.perm_y[((j + dj as i32) & 255) as usize]
+                            ^ self.perm_z[((k + dk as i32) & 255) as usize]
+                    ];
+                }
+            }
+        }
+
+        Perlin::perlin_interp(&c, u, v, w)
+    }
+
+    // Sum of the absolute value of octaves, giving a marbled look
+    pub fn turbulence(&self, p: &Point, depth: u32) -> f32 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p = temp_p * 2.0;
+        }
+
+        accum.abs()
+    }
+
+    // Fractional Brownian motion: signed octaves with a configurable frequency step and amplitude falloff
+    pub fn fbm(&self, p: &Point, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut amplitude = 1.0;
+
+        for _ in 0..octaves {
+            accum += amplitude * self.noise(&temp_p);
+            amplitude *= gain;
+            temp_p = temp_p * lacunarity;
+        }
+
+        accum
+    }
+
+    fn generate_perm(rand: &mut RandomGenerator) -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+        rand.shuffle(&mut perm);
+        perm
+    }
+
+    fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
+        // Hermite smoothing hides the lattice
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+        let mut accum = 0.0;
+
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f32, j as f32, k as f32);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * Vec3::dot(corner, &weight);
+                }
+            }
+        }
+
+        accum
+    }
+}
+
+// Cellular noise with one random feature point per unit cell
+#[derive(Debug)]
+pub struct Worley {
+    offsets: Vec<Vec3>,
+    perm: Vec<usize>,
+}
+
+impl Worley {
+    pub fn new(rand: &mut RandomGenerator) -> Self {
+        let offsets = (0..POINT_COUNT).map(|_| rand.random_vec3_range(0.0, 1.0)).collect();
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+        rand.shuffle(&mut perm);
+
+        Worley { offsets, perm }
+    }
+
+    // Distance to the closest feature point, and an id in [0, 1) for the cell that point belongs to
+    pub fn cell(&self, p: &Point) -> (f32, f32) {
+        let i = p.x.floor() as i32;
+        let j = p.y.floor() as i32;
+        let k = p.z.floor() as i32;
+
+        let mut closest = f32::MAX;
+        let mut id = 0;
+
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let hash = self.hash(ci, cj, ck);
+                    let feature = Vec3::new(ci as f32, cj as f32, ck as f32) + self.offsets[hash];
+
+                    let distance = (feature - *p).length_squared();
+                    if distance < closest {
+                        closest = distance;
+                        id = hash;
+                    }
+                }
+            }
+        }
+
+        (closest.sqrt(), id as f32 / POINT_COUNT as f32)
+    }
+
+    fn hash(&self, i: i32, j: i32, k: i32) -> usize {
+        let a = self.perm[(i & 255) as usize];
+        let b = self.perm[(a ^ (j & 255) as usize) & 255];
+        self.perm[(b ^ (k & 255) as usize) & 255]
+    }
+}
@@ -0,0 +1,614 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::hittable::{Aabb, HitRecord, Hittable, Interval};
+use crate::material::Material;
+use crate::random::RandomGenerator;
+use crate::util::{Onb, Ray, Vec3};
+
+use Vec3 as Point;
+
+
+// ----------------------------------------------
+// Local frames ---------------------------------
+// ----------------------------------------------
+
+// Quadrics are intersected in a frame around their axis, with w along the axis and the origin at their base or centre
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    origin: Point,
+    onb: Onb,
+}
+
+// A hit in the frame, before it goes into the record
+struct Surface {
+    t: f32,
+    normal: Vec3, // Outward, not necessarily of unit length
+    u: f32,
+    v: f32,
+    dpdu: Vec3,
+    dpdv: Vec3,
+}
+
+impl Frame {
+    fn new(origin: Point, axis: &Vec3) -> Self {
+        Frame { origin, onb: Onb::new(&axis.unit_vector()) }
+    }
+
+    // The ray in the frame. The direction keeps its length, so distances along the ray are the same as in the world.
+    fn to_local(self, r: &Ray) -> (Point, Vec3) {
+        (self.onb.to_local(&(*r.origin() - self.origin)), self.onb.to_local(r.direction()))
+    }
+
+    // Box around a circle across the axis, `height` along it
+    fn circle_bounds(self, height: f32, radius: f32) -> Aabb {
+        let center = self.origin + self.onb.w * height;
+        let w = self.onb.w;
+        let extent = Vec3::new((1.0 - w.x * w.x).max(0.0).sqrt(), (1.0 - w.y * w.y).max(0.0).sqrt(), (1.0 - w.z * w.z).max(0.0).sqrt()) * radius;
+        Aabb::new(center - extent, center + extent)
+    }
+
+    // Records the nearest of the hits the material is there for
+    fn closest(self, r: &Ray, mut surfaces: Vec<Surface>, material: &dyn Material, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        surfaces.sort_by(|a, b| a.t.total_cmp(&b.t));
+        surfaces.iter().any(|surface| {
+            rec.t = surface.t;
+            rec.point = r.at(surface.t);
+            rec.material = material.clone();
+            rec.set_face_normal(r, &self.onb.to_world(&surface.normal).unit_vector());
+            rec.set_surface_coords(surface.u, surface.v, self.onb.to_world(&surface.dpdu), self.onb.to_world(&surface.dpdv));
+            rec.is_opaque(rand)
+        })
+    }
+}
+
+// Angle around the axis as u, and how the point moves with it
+fn around_axis(p: &Point) -> (f32, Vec3) {
+    let mut phi = p.y.atan2(p.x);
+    if phi < 0.0 {
+        phi += 2.0 * PI;
+    }
+
+    // The angle collapses on the axis itself, so pick any tangent there, as spheres do at their poles
+    let dpdu = if p.x * p.x + p.y * p.y < 1e-8 { Vec3::new(0.0, 1e-4, 0.0) } else { Vec3::new(-p.y, p.x, 0.0) };
+    (phi / (2.0 * PI), dpdu * (2.0 * PI))
+}
+
+// Hit with a disk or ring across the axis at height `z`, facing up the axis for a `side` of 1 and down it for -1.
+// v runs from 0 on the outer edge to 1 on the inner one.
+fn disk_hit(o: &Point, d: &Vec3, z: f32, side: f32, radius: f32, inner_radius: f32, interval: &Interval) -> Option<Surface> {
+    if d.z == 0.0 {
+        return None;
+    }
+
+    let t = (z - o.z) / d.z;
+    if !interval.surrounds(t) {
+        return None;
+    }
+
+    let p = *o + *d * t;
+    let distance = (p.x * p.x + p.y * p.y).sqrt();
+    if distance > radius || distance < inner_radius {
+        return None;
+    }
+
+    let (u, dpdu) = around_axis(&p);
+    let radial = if distance > 0.0 { Vec3::new(p.x, p.y, 0.0) / distance } else { Vec3::new(1.0, 0.0, 0.0) };
+    Some(Surface {
+        t,
+        normal: Vec3::new(0.0, 0.0, side),
+        u,
+        v: (radius - distance) / (radius - inner_radius),
+        dpdu,
+        dpdv: radial * (inner_radius - radius),
+    })
+}
+
+// Roots of a t² + b t + c in ascending order, computed without cancellation
+fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    let discriminant = b * b - 4.0 * a * c;
+    if a == 0.0 || discriminant < 0.0 {
+        return None;
+    }
+
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    if q == 0.0 {
+        return Some((0.0, 0.0));
+    }
+    let (t0, t1) = (q / a, c / q);
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+
+// ----------------------------------------------
+// Cylinders and cones --------------------------
+// ----------------------------------------------
+
+// Cylinder between the centres of its two ends, closed by flat caps unless opened up into a tube.
+// u goes around the axis, and v along it from the base on the side and from the rim inwards on the caps.
+pub struct Cylinder {
+    frame: Frame,
+    radius: f32,
+    height: f32,
+    capped: bool,
+    material: Box<dyn Material>,
+    bounds: Aabb,
+}
+
+impl Cylinder {
+    pub fn new(base: Point, top: Point, radius: f32, material: Box<dyn Material>) -> Arc<Self> {
+        Cylinder::build(base, top, radius, true, material)
+    }
+
+    // Pipes and tubes, without the caps
+    pub fn open(base: Point, top: Point, radius: f32, material: Box<dyn Material>) -> Arc<Self> {
+        Cylinder::build(base, top, radius, false, material)
+    }
+
+    fn build(base: Point, top: Point, radius: f32, capped: bool, material: Box<dyn Material>) -> Arc<Self> {
+        let axis = top - base;
+        assert!(!axis.near_zero(), "cylinder base and top are at the same point {base:?}");
+        let frame = Frame::new(base, &axis);
+        let (radius, height) = (radius.max(0.0), axis.length());
+        let bounds = Aabb::surrounding(&frame.circle_bounds(0.0, radius), &frame.circle_bounds(height, radius));
+        Arc::new(Cylinder { frame, radius, height, capped, material, bounds })
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        if self.bounds.hit(r, &interval).is_none() {
+            return false;
+        }
+
+        let (o, d) = self.frame.to_local(r);
+        let mut surfaces = Vec::new();
+
+        let a = d.x * d.x + d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.y * d.y);
+        let c = o.x * o.x + o.y * o.y - self.radius * self.radius;
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            for t in [t0, t1] {
+                let p = o + d * t;
+                if interval.surrounds(t) && (0.0..=self.height).contains(&p.z) {
+                    let (u, dpdu) = around_axis(&p);
+                    let dpdv = Vec3::new(0.0, 0.0, self.height);
+                    surfaces.push(Surface { t, normal: Vec3::new(p.x, p.y, 0.0), u, v: p.z / self.height, dpdu, dpdv });
+                }
+            }
+        }
+
+        if self.capped {
+            surfaces.extend(disk_hit(&o, &d, 0.0, -1.0, self.radius, 0.0, &interval));
+            surfaces.extend(disk_hit(&o, &d, self.height, 1.0, self.radius, 0.0, &interval));
+        }
+
+        self.frame.closest(r, surfaces, self.material.as_ref(), rec, rand)
+    }
+}
+
+// Cone from the centre of its base to its tip, closed at the base unless opened up.
+// u goes around the axis, and v from the base to the tip on the side.
+pub struct Cone {
+    frame: Frame,
+    radius: f32,
+    height: f32,
+    capped: bool,
+    material: Box<dyn Material>,
+    bounds: Aabb,
+}
+
+impl Cone {
+    pub fn new(base: Point, apex: Point, radius: f32, material: Box<dyn Material>) -> Arc<Self> {
+        Cone::build(base, apex, radius, true, material)
+    }
+
+    // Funnels and lampshades, without the base
+    pub fn open(base: Point, apex: Point, radius: f32, material: Box<dyn Material>) -> Arc<Self> {
+        Cone::build(base, apex, radius, false, material)
+    }
+
+    fn build(base: Point, apex: Point, radius: f32, capped: bool, material: Box<dyn Material>) -> Arc<Self> {
+        let axis = apex - base;
+        assert!(!axis.near_zero(), "cone base and apex are at the same point {base:?}");
+        let frame = Frame::new(base, &axis);
+        let (radius, height) = (radius.max(0.0), axis.length());
+        let bounds = Aabb::surrounding(&frame.circle_bounds(0.0, radius), &Aabb::new(apex, apex));
+        Arc::new(Cone { frame, radius, height, capped, material, bounds })
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        if self.bounds.hit(r, &interval).is_none() {
+            return false;
+        }
+
+        let (o, d) = self.frame.to_local(r);
+        let mut surfaces = Vec::new();
+
+        // x² + y² = (k (h - z))², with the radius shrinking by k per unit of height
+        let h = self.height;
+        let k2 = (self.radius / h).powi(2);
+        let a = d.x * d.x + d.y * d.y - k2 * d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.y * d.y + k2 * (h - o.z) * d.z);
+        let c = o.x * o.x + o.y * o.y - k2 * (h - o.z) * (h - o.z);
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            for t in [t0, t1] {
+                let p = o + d * t;
+                if interval.surrounds(t) && (0.0..=h).contains(&p.z) {
+                    let (u, dpdu) = around_axis(&p);
+                    let v = p.z / h;
+                    let narrowing = (1.0 - v).max(1e-4);
+                    let dpdv = Vec3::new(-p.x / narrowing, -p.y / narrowing, h);
+                    surfaces.push(Surface { t, normal: Vec3::new(p.x, p.y, k2 * (h - p.z)), u, v, dpdu, dpdv });
+                }
+            }
+        }
+
+        if self.capped {
+            surfaces.extend(disk_hit(&o, &d, 0.0, -1.0, self.radius, 0.0, &interval));
+        }
+
+        self.frame.closest(r, surfaces, self.material.as_ref(), rec, rand)
+    }
+}
+
+
+// ----------------------------------------------
+// Disks ----------------------------------------
+// ----------------------------------------------
+
+// Flat disk, or a ring between two radii like a washer. u goes around the centre and v from the rim inwards.
+pub struct Disk {
+    frame: Frame,
+    radius: f32,
+    inner_radius: f32,
+    material: Box<dyn Material>,
+    bounds: Aabb,
+}
+
+impl Disk {
+    pub fn new(center: Point, normal: Vec3, radius: f32, material: Box<dyn Material>) -> Arc<Self> {
+        Disk::annulus(center, normal, 0.0, radius, material)
+    }
+
+    pub fn annulus(center: Point, normal: Vec3, inner_radius: f32, radius: f32, material: Box<dyn Material>) -> Arc<Self> {
+        assert!(!normal.near_zero(), "disk normal {normal:?} has no direction");
+        let frame = Frame::new(center, &normal);
+        let radius = radius.max(0.0);
+        let inner_radius = inner_radius.clamp(0.0, radius * 0.9999);
+        Arc::new(Disk { frame, radius, inner_radius, material, bounds: frame.circle_bounds(0.0, radius) })
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        if self.bounds.hit(r, &interval).is_none() {
+            return false;
+        }
+
+        let (o, d) = self.frame.to_local(r);
+        let surfaces = disk_hit(&o, &d, 0.0, 1.0, self.radius, self.inner_radius, &interval).into_iter().collect();
+        self.frame.closest(r, surfaces, self.material.as_ref(), rec, rand)
+    }
+}
+
+
+// ----------------------------------------------
+// Tori -----------------------------------------
+// ----------------------------------------------
+
+// Ring around an axis through its centre, with `major_radius` out to the middle of a tube `minor_radius` thick.
+// u goes around the axis and v around the tube, starting on the outside.
+pub struct Torus {
+    frame: Frame,
+    major_radius: f32,
+    minor_radius: f32,
+    material: Box<dyn Material>,
+    bounds: Aabb,
+}
+
+impl Torus {
+    pub fn new(center: Point, axis: Vec3, major_radius: f32, minor_radius: f32, material: Box<dyn Material>) -> Arc<Self> {
+        assert!(!axis.near_zero(), "torus axis {axis:?} has no direction");
+        let frame = Frame::new(center, &axis);
+        let (major_radius, minor_radius) = (major_radius.max(0.0), minor_radius.max(0.0));
+        let outer = major_radius + minor_radius;
+        let bounds = Aabb::surrounding(&frame.circle_bounds(-minor_radius, outer), &frame.circle_bounds(minor_radius, outer));
+        Arc::new(Torus { frame, major_radius, minor_radius, material, bounds })
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        let Some((entry, _)) = self.bounds.hit(r, &interval) else { return false };
+
+        // Solve from where the ray enters the bounds, which keeps the quartic's coefficients small
+        let (o, d) = self.frame.to_local(r);
+        let o = o + d * entry;
+
+        // (x² + y² + z² + R² - r²)² = 4 R² (x² + y²) along the ray
+        let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+        let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+        let major2 = (self.major_radius as f64).powi(2);
+        let minor2 = (self.minor_radius as f64).powi(2);
+
+        let dd = dx * dx + dy * dy + dz * dz;
+        let od = ox * dx + oy * dy + oz * dz;
+        let k = ox * ox + oy * oy + oz * oz + major2 - minor2;
+
+        let a4 = dd * dd;
+        let a3 = 4.0 * dd * od;
+        let a2 = 2.0 * dd * k + 4.0 * od * od - 4.0 * major2 * (dx * dx + dy * dy);
+        let a1 = 4.0 * k * od - 8.0 * major2 * (ox * dx + oy * dy);
+        let a0 = k * k - 4.0 * major2 * (ox * ox + oy * oy);
+
+        let mut surfaces = Vec::new();
+        for root in solve_quartic(a3 / a4, a2 / a4, a1 / a4, a0 / a4) {
+            let t = entry + root as f32;
+            if !interval.surrounds(t) {
+                continue;
+            }
+
+            let p = o + d * root as f32;
+            let rho = (p.x * p.x + p.y * p.y).sqrt().max(1e-6);
+            let (cos_phi, sin_phi) = (p.x / rho, p.y / rho);
+            let tube_center = Vec3::new(cos_phi, sin_phi, 0.0) * self.major_radius;
+
+            let mut theta = p.z.atan2(rho - self.major_radius);
+            if theta < 0.0 {
+                theta += 2.0 * PI;
+            }
+            let (u, dpdu) = around_axis(&p);
+            let dpdv = Vec3::new(-theta.sin() * cos_phi, -theta.sin() * sin_phi, theta.cos()) * (2.0 * PI * self.minor_radius);
+            surfaces.push(Surface { t, normal: p - tube_center, u, v: theta / (2.0 * PI), dpdu, dpdv });
+        }
+
+        self.frame.closest(r, surfaces, self.material.as_ref(), rec, rand)
+    }
+}
+
+// Real roots of t⁴ + b t³ + c t² + d t + e in ascending order, by Ferrari's method polished with Newton steps.
+// Works in f64, since a torus' coefficients span many orders of magnitude.
+fn solve_quartic(b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+
+    // Depressed quartic y⁴ + p y² + q y + r, with t = y - b / 4
+    let shift = b / 4.0;
+    let b2 = b * b;
+    let p = c - 3.0 * b2 / 8.0;
+    let q = d - b * c / 2.0 + b2 * b / 8.0;
+    let r = e - b * d / 4.0 + b2 * c / 16.0 - 3.0 * b2 * b2 / 256.0;
+
+    // Real roots of y² + bq y + cq
+    fn quadratic(roots: &mut Vec<f64>, bq: f64, cq: f64) {
+        let discriminant = bq * bq - 4.0 * cq;
+        if discriminant >= 0.0 {
+            let s = discriminant.sqrt();
+            roots.push((-bq - s) / 2.0);
+            roots.push((-bq + s) / 2.0);
+        }
+    }
+
+    let mut roots = Vec::with_capacity(4);
+    if q.abs() < 1e-12 {
+        // Biquadratic, so a quadratic in y²
+        let discriminant = p * p - 4.0 * r;
+        if discriminant >= 0.0 {
+            let s = discriminant.sqrt();
+            for y2 in [(-p - s) / 2.0, (-p + s) / 2.0] {
+                if y2 >= 0.0 {
+                    roots.push(-y2.sqrt());
+                    roots.push(y2.sqrt());
+                }
+            }
+        }
+    } else {
+        // A positive root m of the resolvent cubic splits the quartic into two quadratics
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return roots;
+        }
+        let s = (2.0 * m).sqrt();
+        quadratic(&mut roots, -s, p / 2.0 + m + q / (2.0 * s));
+        quadratic(&mut roots, s, p / 2.0 + m - q / (2.0 * s));
+    }
+
+    for t in roots.iter_mut() {
+        *t -= shift;
+        for _ in 0..2 {
+            let f = (((*t + b) * *t + c) * *t + d) * *t + e;
+            let df = ((4.0 * *t + 3.0 * b) * *t + 2.0 * c) * *t + d;
+            if df != 0.0 {
+                *t -= f / df;
+            }
+        }
+    }
+
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// Largest real root of x³ + a x² + b x + c, by Cardano's formula or its trigonometric form for three real roots
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+
+    let y = if discriminant > 0.0 {
+        let s = discriminant.sqrt();
+        (-q / 2.0 + s).cbrt() + (-q / 2.0 - s).cbrt()
+    } else if p < 0.0 {
+        let radius = (-p / 3.0).sqrt();
+        let angle = (-q / (2.0 * radius * radius * radius)).clamp(-1.0, 1.0).acos() / 3.0;
+        2.0 * radius * angle.cos()
+    } else {
+        0.0
+    };
+
+    let mut x = y - a / 3.0;
+    for _ in 0..2 {
+        let f = ((x + a) * x + b) * x + c;
+        let df = (3.0 * x + 2.0 * a) * x + b;
+        if df != 0.0 {
+            x -= f / df;
+        }
+    }
+    x
+}
+
+
+// ----------------------------------------------
+// Planes ---------------------------------------
+// ----------------------------------------------
+
+// Infinite plane through a point. It has no bounds, and (u, v) are distances along it in world units.
+pub struct Plane {
+    frame: Frame,
+    material: Box<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Point, normal: Vec3, material: Box<dyn Material>) -> Arc<Self> {
+        assert!(!normal.near_zero(), "plane normal {normal:?} has no direction");
+        Arc::new(Plane { frame: Frame::new(point, &normal), material })
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        let (o, d) = self.frame.to_local(r);
+        if d.z == 0.0 {
+            return false;
+        }
+
+        let t = -o.z / d.z;
+        if !interval.surrounds(t) {
+            return false;
+        }
+
+        let p = o + d * t;
+        let surface = Surface { t, normal: Vec3::new(0.0, 0.0, 1.0), u: p.x, v: p.y, dpdu: Vec3::new(1.0, 0.0, 0.0), dpdv: Vec3::new(0.0, 1.0, 0.0) };
+        self.frame.closest(r, vec![surface], self.material.as_ref(), rec, rand)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn grey() -> Box<dyn Material> {
+        Lambertian::new(Vec3::new(0.5, 0.5, 0.5))
+    }
+
+    fn hit(object: &dyn Hittable, origin: Point, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::new();
+        let ray = Ray::new(origin, direction);
+        object.hit(&ray, Interval::new(0.001, f32::INFINITY), &mut rec, &mut RandomGenerator::seeded(1)).then_some(rec)
+    }
+
+    fn assert_close(a: &Vec3, b: &Vec3, tolerance: f32) {
+        assert!((*a - *b).length() < tolerance, "{a:?} against {b:?}");
+    }
+
+    fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+        assert_eq!(roots.len(), expected.len(), "{roots:?}");
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-9, "{roots:?} against {expected:?}");
+        }
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        assert_roots(solve_quartic(-10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0]);
+        // (t² - 1)(t² - 4), with no odd terms
+        assert_roots(solve_quartic(0.0, -5.0, 0.0, 4.0), &[-2.0, -1.0, 1.0, 2.0]);
+        // (t + 0.5)(t - 3)(t² + 1), two of them complex
+        assert_roots(solve_quartic(-2.5, -0.5, -2.5, -1.5), &[-0.5, 3.0]);
+        assert_roots(solve_quartic(0.0, 0.0, 0.0, 1.0), &[]);
+    }
+
+    #[test]
+    fn torus_hits() {
+        let torus = Torus::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 2.0, 0.5, grey());
+
+        let rec = hit(torus.as_ref(), Point::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).expect("outer side");
+        assert!((rec.t - 2.5).abs() < 1e-4 && rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(-1.0, 0.0, 0.0), 1e-4);
+
+        // Far away rays still find the tube, and pass through the hole along the axis
+        let rec = hit(torus.as_ref(), Point::new(0.0, -1000.0, 0.2), Vec3::new(0.0, 1.0, 0.0)).expect("far ray");
+        let tube = (0.25_f32 - 0.04).sqrt();
+        assert!((rec.t - (1000.0 - 2.0 - tube)).abs() < 1e-2, "{}", rec.t);
+        assert!(hit(torus.as_ref(), Point::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+
+        // From inside the tube the ray leaves through its far wall
+        let rec = hit(torus.as_ref(), Point::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)).expect("inside");
+        assert!((rec.t - 0.5).abs() < 1e-4 && !rec.front_face);
+    }
+
+    #[test]
+    fn cylinder_hits_side_and_caps() {
+        let (base, top) = (Point::new(0.0, 0.0, 0.0), Point::new(0.0, 2.0, 0.0));
+        let cylinder = Cylinder::new(base, top, 1.0, grey());
+
+        let rec = hit(cylinder.as_ref(), Point::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).expect("side");
+        assert!((rec.t - 4.0).abs() < 1e-5 && rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(1.0, 0.0, 0.0), 1e-5);
+        assert!((rec.v - 0.5).abs() < 1e-5);
+
+        let rec = hit(cylinder.as_ref(), Point::new(0.3, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).expect("top cap");
+        assert!((rec.t - 3.0).abs() < 1e-5 && rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(0.0, 1.0, 0.0), 1e-5);
+
+        // Without caps the same ray runs down inside the tube
+        let tube = Cylinder::open(base, top, 1.0, grey());
+        assert!(hit(tube.as_ref(), Point::new(0.3, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+        let rec = hit(tube.as_ref(), Point::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).expect("inside");
+        assert!((rec.t - 1.0).abs() < 1e-5 && !rec.front_face);
+    }
+
+    #[test]
+    fn cone_hits_side_and_base() {
+        let cone = Cone::new(Point::new(0.0, 0.0, 0.0), Point::new(0.0, 2.0, 0.0), 1.0, grey());
+
+        // Halfway up the radius is halved, and the normal leans towards the apex
+        let rec = hit(cone.as_ref(), Point::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).expect("side");
+        assert!((rec.t - 4.5).abs() < 1e-5 && rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(2.0, 1.0, 0.0).unit_vector(), 1e-4);
+
+        let rec = hit(cone.as_ref(), Point::new(0.2, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).expect("base");
+        assert!((rec.t - 5.0).abs() < 1e-5 && rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(0.0, -1.0, 0.0), 1e-5);
+
+        assert!(hit(cone.as_ref(), Point::new(5.0, 2.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn annulus_hits_between_radii() {
+        let ring = Disk::annulus(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), 0.5, 1.0, grey());
+
+        let rec = hit(ring.as_ref(), Point::new(0.75, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).expect("ring");
+        assert!((rec.t - 5.0).abs() < 1e-5 && rec.front_face);
+        assert_close(&rec.normal, &Vec3::new(0.0, 0.0, 1.0), 1e-6);
+        assert!((rec.v - 0.5).abs() < 1e-5);
+
+        assert!(hit(ring.as_ref(), Point::new(0.25, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(hit(ring.as_ref(), Point::new(1.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "same point")]
+    fn cylinder_rejects_zero_axis() {
+        Cylinder::new(Point::new(1.0, 1.0, 1.0), Point::new(1.0, 1.0, 1.0), 1.0, grey());
+    }
+
+    #[test]
+    #[should_panic(expected = "no direction")]
+    fn disk_rejects_zero_normal() {
+        Disk::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0), 1.0, grey());
+    }
+}
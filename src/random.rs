@@ -1,17 +1,27 @@
 use rand::distr::{Distribution, SampleString, Uniform};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 use rand_distr::Alphanumeric; // Ensure you import the correct Uniform type
 
-use crate::util::Vec3;
+use crate::util::{Onb, Vec3};
 
 #[derive(Clone)]
 pub struct RandomGenerator {
-    rng: rand::rngs::ThreadRng,
+    rng: ChaCha8Rng,
 }
 
 impl RandomGenerator {
     pub fn new() -> Self {
         RandomGenerator {
-            rng: rand::rng(),
+            rng: ChaCha8Rng::from_rng(&mut rand::rng()),
+        }
+    }
+
+    // Deterministic generator, so procedural content comes out the same on every run
+    pub fn seeded(seed: u64) -> Self {
+        RandomGenerator {
+            rng: ChaCha8Rng::seed_from_u64(seed),
         }
     }
 
@@ -60,6 +70,28 @@ impl RandomGenerator {
         }
     }
 
+    // Uniformly samples a direction within the cone around `axis` whose half angle has cosine `cos_max`
+    pub fn random_in_cone(&mut self, axis: &Vec3, cos_max: f32) -> Vec3 {
+        let cos_theta = 1.0 - self.random_float() * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * self.random_float();
+
+        Onb::new(axis).to_world(&Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+
+    // Direction in the hemisphere around +z with density cos(theta) / pi, from a point on the disk
+    // projected up (Malley's method). Use an `Onb` to move it into place.
+    pub fn random_cosine_direction(&mut self) -> Vec3 {
+        let r = self.random_float().sqrt();
+        let phi = 2.0 * std::f32::consts::PI * self.random_float();
+        let (x, y) = (r * phi.cos(), r * phi.sin());
+        Vec3::new(x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
+    }
+
+    pub fn shuffle<T>(&mut self, values: &mut [T]) {
+        values.shuffle(&mut self.rng);
+    }
+
     pub fn random_chars(&mut self, len: usize) -> String {
         Alphanumeric.sample_string(&mut self.rng, len)
     }
@@ -0,0 +1,707 @@
+use crate::camera::Camera;
+use crate::environment::{EnvironmentMap, Fog, Gradient, Sky};
+use crate::hittable::{make_box, ConstantMedium, HittableList, Quad, Sphere};
+use crate::light::{DirectionalLight, Emission, LightList, PointLight, SpotLight};
+use crate::material::{AlphaMask, BumpMap, Clearcoat, Conductor, Dielectric, Lambertian, MaterialLibrary, Metal, MixMaterial, NormalMap, OrenNayar, Phase, Principled, PrincipledParams, RoughDielectric, Subsurface, ThinFilm};
+use crate::noise::Perlin;
+use crate::quadric::{Cone, Cylinder, Disk, Plane, Torus};
+use crate::random::RandomGenerator;
+use crate::spectrum::{Dispersion, Spectrum};
+use crate::texture::{Checker, ImageTexture, WrapMode, Marble, NoiseKind, NoiseTexture, SolidColor, Texture, Voronoi, Wood};
+use crate::util::Vec3;
+use crate::volume::{DensityGrid, Glow, GridMedium};
+
+use std::sync::Arc;
+
+use Vec3 as Point;
+use Vec3 as Color;
+
+// Field of small random spheres around three large ones
+pub fn bouncing_spheres(rand: &mut RandomGenerator) -> (Camera, HittableList, LightList) {
+
+    // Camera parameters
+    let fov = 20.0;
+    let look_from = Point::new(13.0, 2.0, 3.0);
+    let look_at = Point::new(0.0, 0.0, 0.0);
+
+    // Initialize camera
+    let camera = Camera::initialize(fov, look_from, look_at);
+
+    // Set up scene
+    let mut world = HittableList::new();
+
+    // The glass is the same for every small sphere, so they all share it
+    let mut library = MaterialLibrary::new();
+    library.add("glass", Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.5));
+    let glass = || library.get("glass").expect("glass is in the library");
+
+    let mat_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, mat_ground));
+
+    for a in -22..22{
+        for b in -11..11 {
+            let choose_mat = &rand.random_float();
+
+            let center = Point::new(a as f32 + 0.9 * &rand.random_float(), 0.2, b as f32 + 0.9 * &rand.random_float());
+
+            if (center - Point::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                if *choose_mat < 0.8 {
+                    // diffuse
+                    let albedo = &rand.random_vec3_range(0.0, 1.0) * &rand.random_vec3_range(0.0, 1.0);
+                    let mat = Lambertian::new(albedo);
+                    world.add(Sphere::new(center, 0.2, mat));
+                } else if *choose_mat < 0.95 {
+                    // Metal
+                    let albedo = &rand.random_vec3_range(0.5, 1.0);
+                    let fuzz = &rand.random_float_range(0.0, 0.5);
+                    let mat = Metal::new(*albedo, *fuzz);
+                    world.add(Sphere::new(center, 0.2, mat));
+                } else {
+                    // Glass
+                    world.add(Sphere::new(center, 0.2, glass()));
+                }
+            }
+        }
+    }
+
+    let mat_1 = Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.33);
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, mat_1));
+
+    let mat_2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
+    world.add(Sphere::new(Point::new(-4.0, 1.0, 0.0), 1.0, mat_2));
+
+    let mat_3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
+    world.add(Sphere::new(Point::new(4.0, 1.0, 0.0), 1.0, mat_3));
+
+    (camera, world, LightList::new())
+}
+
+// Product shot lit only by an equirectangular HDR environment
+pub fn hdri(env_path: &str, intensity: f32, rotation: f32) -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(25.0, Point::new(0.0, 1.5, 9.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = EnvironmentMap::load(env_path, intensity, rotation)
+        .unwrap_or_else(|err| panic!("Unable to load environment map {}: {}", env_path, err));
+
+    let mut world = HittableList::new();
+
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(Color::new(0.6, 0.6, 0.6))));
+    world.add(Sphere::new(Point::new(-2.1, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.7, 0.2, 0.1))));
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, Metal::new(Color::new(0.9, 0.9, 0.9), 0.0)));
+    world.add(Sphere::new(Point::new(2.1, 1.0, 0.0), 1.0, Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.5)));
+
+    (camera, world, LightList::new())
+}
+
+// Outdoor scene under a physical sky, with the sun placed by the local time of day
+pub fn sky(hour: f32, turbidity: f32) -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(25.0, Point::new(0.0, 1.5, 9.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::at_time(hour, 45.0, 172, turbidity, 0.03);
+
+    let mut world = HittableList::new();
+
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    world.add(Sphere::new(Point::new(-2.1, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.8, 0.8, 0.8))));
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, Metal::new(Color::new(0.9, 0.9, 0.9), 0.0)));
+    world.add(Sphere::new(Point::new(2.1, 1.0, 0.0), 1.0, Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.5)));
+
+    (camera, world, LightList::new())
+}
+
+// Night scene lit by a warm point light, a cool spot light and a dim moonlight
+pub fn lights() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(25.0, Point::new(0.0, 1.5, 9.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Gradient::new(Color::new(0.0, 0.0, 0.0), Color::new(0.01, 0.01, 0.02));
+
+    let mut world = HittableList::new();
+
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    world.add(Sphere::new(Point::new(-2.1, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.8, 0.8, 0.8))));
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.8, 0.3, 0.3))));
+    world.add(Sphere::new(Point::new(2.1, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.3, 0.3, 0.8))));
+
+    let mut lights = LightList::new();
+
+    let warm = Emission::new(Color::new(1.0, 1.0, 1.0), 20.0).with_temperature(2700.0);
+    lights.add(PointLight::new(Point::new(-3.0, 4.0, 2.0), 0.3, warm));
+
+    let cool = Emission::new(Color::new(1.0, 1.0, 1.0), 40.0).with_temperature(8000.0);
+    lights.add(SpotLight::new(Point::new(3.0, 5.0, 1.0), Vec3::new(-0.4, -1.0, -0.2), 15.0, 25.0, 0.1, cool));
+
+    let moon = Emission::new(Color::new(0.6, 0.7, 1.0), 0.1);
+    lights.add(DirectionalLight::new(Vec3::new(1.0, -1.0, -0.5), 0.5, moon));
+
+    (camera, world, lights)
+}
+
+// Checkered ground with textured spheres, optionally wrapping an image around the middle one
+pub fn textures(image_path: Option<&str>, wrap: WrapMode) -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(25.0, Point::new(0.0, 1.5, 9.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let middle: Arc<dyn Texture> = match image_path {
+        Some(path) => ImageTexture::load(path, wrap, true).unwrap_or_else(|err| panic!("Unable to load texture {}: {}", path, err)),
+        None => Checker::from_colors(0.25, Color::new(0.8, 0.1, 0.1), Color::new(0.9, 0.9, 0.9)),
+    };
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, Lambertian::textured(middle)));
+
+    // Polished and brushed squares on the same metal
+    let fuzz = Checker::new(0.3, SolidColor::scalar(0.0), SolidColor::scalar(0.4));
+    world.add(Sphere::new(Point::new(-2.1, 1.0, 0.0), 1.0, Metal::textured(SolidColor::new(Color::new(0.8, 0.8, 0.8)), fuzz)));
+
+    let tint = Checker::from_colors(0.3, Color::new(1.0, 1.0, 1.0), Color::new(0.7, 0.9, 1.0));
+    world.add(Sphere::new(Point::new(2.1, 1.0, 0.0), 1.0, Dielectric::textured(tint, 1.5)));
+
+    (camera, world, LightList::new())
+}
+
+// Procedural look-dev: every texture here comes from seeded noise
+pub fn noise(seed: u64) -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.5, 10.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+
+    let mut rand = RandomGenerator::seeded(seed);
+    let mut world = HittableList::new();
+
+    let ground = NoiseTexture::new(&mut rand, NoiseKind::Fbm, 2.0, 6, Color::new(0.6, 0.5, 0.4));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let marble = Marble::new(&mut rand, 4.0, Color::new(0.9, 0.9, 0.88), Color::new(0.2, 0.2, 0.25));
+    world.add(Sphere::new(Point::new(-3.3, 1.0, 0.0), 1.0, Lambertian::textured(marble)));
+
+    let wood = Wood::new(&mut rand, 6.0, Color::new(0.75, 0.5, 0.3), Color::new(0.35, 0.18, 0.08));
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, Lambertian::textured(wood)));
+
+    let cells = Voronoi::new(&mut rand, 3.0, Color::new(0.2, 0.6, 0.3), Color::new(0.05, 0.05, 0.05));
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, Lambertian::textured(cells)));
+
+    // Turbulence breaks up the polish of the metal, plain noise its tint
+    let tint = NoiseTexture::new(&mut rand, NoiseKind::Perlin, 3.0, 1, Color::new(0.9, 0.8, 0.6));
+    let fuzz = NoiseTexture::new(&mut rand, NoiseKind::Turbulence, 4.0, 5, Color::new(0.3, 0.3, 0.3));
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, Metal::textured(tint, fuzz)));
+
+    (camera, world, LightList::new())
+}
+
+// Bump mapped spheres, and an optional tangent space normal map on the right
+pub fn bump(normal_map_path: Option<&str>) -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(25.0, Point::new(0.0, 1.5, 9.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+
+    let mut rand = RandomGenerator::seeded(1);
+    let mut world = HittableList::new();
+
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+
+    let dents = Voronoi::new(&mut rand, 4.0, Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0));
+    let hammered = BumpMap::new(Metal::new(Color::new(0.8, 0.6, 0.4), 0.0), dents, 0.05);
+    world.add(Sphere::new(Point::new(-2.1, 1.0, 0.0), 1.0, hammered));
+
+    let grain = NoiseTexture::new(&mut rand, NoiseKind::Turbulence, 6.0, 6, Color::new(1.0, 1.0, 1.0));
+    let stucco = BumpMap::new(Lambertian::new(Color::new(0.8, 0.75, 0.7)), grain, 0.01);
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, stucco));
+
+    // A flat normal pointing straight out of the surface stands in when no map is given
+    let map: Arc<dyn Texture> = match normal_map_path {
+        Some(path) => ImageTexture::load(path, WrapMode::Repeat, false).unwrap_or_else(|err| panic!("Unable to load normal map {}: {}", path, err)),
+        None => SolidColor::new(Color::new(0.5, 0.5, 1.0)),
+    };
+    world.add(Sphere::new(Point::new(2.1, 1.0, 0.0), 1.0, NormalMap::new(Lambertian::new(Color::new(0.3, 0.5, 0.8)), map, 1.0)));
+
+    // A low key light from the side brings out the relief
+    let mut lights = LightList::new();
+    lights.add(DirectionalLight::new(Vec3::new(1.0, -0.5, -0.6), 1.0, Emission::new(Color::new(1.0, 1.0, 1.0), 2.0)));
+
+    (camera, world, lights)
+}
+
+// Measured metals under a physical sky, from polished to brushed
+pub fn metals() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.5, 10.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(35.0, 200.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(1.0, Color::new(0.2, 0.2, 0.2), Color::new(0.8, 0.8, 0.8));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    world.add(Sphere::new(Point::new(-3.3, 1.0, 0.0), 1.0, Conductor::gold(0.1)));
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, Conductor::silver(0.3)));
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, Conductor::copper(0.5)));
+
+    // Brushed aluminium, with the grooves running around the sphere
+    let brushed = Conductor::new(Color::new(1.657, 0.880, 0.521), Color::new(9.224, 6.270, 4.837), 0.4, 0.9);
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, brushed));
+
+    world.add(Sphere::new(Point::new(0.0, 0.4, 2.0), 0.4, Conductor::aluminium(0.0)));
+
+    (camera, world, LightList::new())
+}
+
+// Glass from polished to frosted in front of a checker board, with colored and thin-walled glass up front
+pub fn glass() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 10.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(40.0, 160.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.4, Color::new(0.1, 0.1, 0.1), Color::new(0.9, 0.9, 0.9));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    world.add(Sphere::new(Point::new(-3.3, 1.0, 0.0), 1.0, Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.5)));
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, RoughDielectric::new(1.5, 0.1)));
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, RoughDielectric::new(1.5, 0.3)));
+
+    // Frosting that wears off towards the top
+    let frost = NoiseTexture::new(&mut RandomGenerator::seeded(2), NoiseKind::Fbm, 3.0, 4, Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, RoughDielectric::textured(1.5, frost)));
+
+    // Colored glass gets darker where it is thicker, a thin-walled bubble does not bend light
+    let green = Dielectric::new(Color::new(0.3, 0.8, 0.4), 1.5).with_absorption_distance(0.5);
+    world.add(Sphere::new(Point::new(-1.2, 0.6, 2.2), 0.6, green));
+    world.add(Sphere::new(Point::new(1.2, 0.6, 2.2), 0.6, Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.33).thin_walled()));
+
+    (camera, world, LightList::new())
+}
+
+// Dispersive glass and a diamond, with the low sun throwing rainbow caustics
+pub fn dispersion() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.5, 9.0), Point::new(0.0, 0.7, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(25.0, 180.0, 2.5, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.6, 0.6, 0.6), Color::new(0.8, 0.8, 0.8));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let white = Color::new(1.0, 1.0, 1.0);
+    world.add(Sphere::new(Point::new(-2.4, 1.0, 0.0), 1.0, Dielectric::new(white, 1.5).with_dispersion(Dispersion::bk7())));
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, Dielectric::new(white, 1.5).with_dispersion(Dispersion::dense_flint())));
+    world.add(Sphere::new(Point::new(2.4, 1.0, 0.0), 1.0, Dielectric::new(white, 1.5).with_dispersion(Dispersion::diamond())));
+
+    // Extremely dispersive made-up glass to show off the effect
+    let cauchy = Dispersion::Cauchy { a: 1.45, b: 0.05 };
+    world.add(Sphere::new(Point::new(0.0, 0.5, 2.2), 0.5, Dielectric::new(white, 1.5).with_dispersion(cauchy)));
+
+    (camera, world, LightList::new())
+}
+
+// Colored spheres under a sodium street lamp and a warm black body light. Rendered with --spectral,
+// the narrow band of the sodium lamp washes the colors out in a way RGB rendering can't reproduce.
+pub fn spectral() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 9.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Gradient::new(Color::new(0.02, 0.02, 0.03), Color::new(0.01, 0.01, 0.02));
+
+    let mut world = HittableList::new();
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+
+    let colors = [Color::new(0.8, 0.1, 0.1), Color::new(0.1, 0.7, 0.1), Color::new(0.1, 0.2, 0.8), Color::new(0.8, 0.7, 0.1)];
+    for (i, color) in colors.into_iter().enumerate() {
+        let x = -3.0 + 2.0 * i as f32;
+        world.add(Sphere::new(Point::new(x, 0.8, 0.0), 0.8, Lambertian::new(color)));
+    }
+
+    let mut lights = LightList::new();
+
+    // High pressure sodium, a broad orange band around the 589 nm line
+    let sodium = Spectrum::tabulated(vec![(540.0, 0.0), (565.0, 0.3), (580.0, 0.6), (589.0, 1.0), (600.0, 0.6), (620.0, 0.25), (650.0, 0.0)]);
+    lights.add(PointLight::new(Point::new(-4.0, 5.0, 2.0), 0.3, Emission::new(Color::new(1.0, 1.0, 1.0), 40.0).with_spectrum(sodium)));
+    lights.add(PointLight::new(Point::new(4.0, 5.0, 2.0), 0.3, Emission::new(Color::new(1.0, 1.0, 1.0), 30.0).with_temperature(4000.0)));
+
+    (camera, world, lights)
+}
+
+// The principled material dressed up as plastic, metal, glass, car paint, velvet and wax
+pub fn principled() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(35.0, Point::new(0.0, 2.5, 14.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(35.0, 140.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.2, 0.2), Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let color = |r, g, b| -> Arc<dyn Texture> { SolidColor::new(Color::new(r, g, b)) };
+    let scalar = SolidColor::scalar;
+
+    let plastic = PrincipledParams { base_color: color(0.7, 0.1, 0.1), roughness: scalar(0.3), ..Default::default() };
+    let metal = PrincipledParams { base_color: color(0.95, 0.75, 0.4), metallic: scalar(1.0), roughness: scalar(0.25), ..Default::default() };
+    let glass = PrincipledParams { base_color: color(0.9, 1.0, 0.95), transmission: scalar(1.0), roughness: scalar(0.05), ..Default::default() };
+
+    let flakes = NoiseTexture::new(&mut RandomGenerator::seeded(5), NoiseKind::Turbulence, 40.0, 2, Color::new(0.6, 0.6, 0.6));
+    let car_paint = PrincipledParams {
+        base_color: color(0.05, 0.15, 0.5),
+        metallic: scalar(0.6),
+        roughness: flakes,
+        clearcoat: scalar(1.0),
+        clearcoat_roughness: scalar(0.02),
+        ..Default::default()
+    };
+
+    let velvet = PrincipledParams { base_color: color(0.3, 0.02, 0.15), roughness: scalar(1.0), specular: scalar(0.2), sheen: scalar(1.0), sheen_color: color(1.0, 0.7, 0.85), ..Default::default() };
+    let wax = PrincipledParams { base_color: color(0.9, 0.8, 0.6), roughness: scalar(0.6), subsurface: scalar(1.0), ..Default::default() };
+
+    for (i, params) in [plastic, metal, glass, car_paint, velvet, wax].into_iter().enumerate() {
+        let x = -5.5 + 2.2 * i as f32;
+        world.add(Sphere::new(Point::new(x, 1.0, 0.0), 1.0, Principled::new(params)));
+    }
+
+    (camera, world, LightList::new())
+}
+
+// Varnished wood, car paint, amber lacquer and coated gold
+pub fn clearcoat() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 11.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(30.0, 200.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.2, 0.2), Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let mut rand = RandomGenerator::seeded(11);
+    let wood = Wood::new(&mut rand, 6.0, Color::new(0.6, 0.4, 0.2), Color::new(0.3, 0.15, 0.05));
+    let varnish = Clearcoat::new(Lambertian::textured(wood), 0.05, 1.5).with_absorption(Color::new(0.95, 0.85, 0.6));
+    world.add(Sphere::new(Point::new(-3.3, 1.0, 0.0), 1.0, varnish));
+
+    let car_paint = Clearcoat::new(Metal::new(Color::new(0.1, 0.25, 0.6), 0.4), 0.02, 1.5);
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, car_paint));
+
+    let lacquer = Clearcoat::new(Lambertian::new(Color::new(0.6, 0.05, 0.05)), 0.15, 1.5);
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, lacquer));
+
+    let coated_gold = Clearcoat::new(Conductor::gold(0.35), 0.0, 1.6).with_absorption(Color::new(0.8, 0.9, 1.0));
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, coated_gold));
+
+    (camera, world, LightList::new())
+}
+
+// Lambertian next to Oren-Nayar spheres of growing roughness, lit from the side to show the flattening
+pub fn rough_diffuse() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 11.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Gradient::new(Color::new(0.05, 0.05, 0.05), Color::new(0.05, 0.05, 0.05));
+
+    let mut world = HittableList::new();
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, OrenNayar::new(Color::new(0.5, 0.5, 0.5), 30.0)));
+
+    let clay = Color::new(0.75, 0.45, 0.3);
+    world.add(Sphere::new(Point::new(-3.3, 1.0, 0.0), 1.0, Lambertian::new(clay)));
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, OrenNayar::new(clay, 15.0)));
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, OrenNayar::new(clay, 30.0)));
+
+    let fabric = Checker::from_colors(0.08, Color::new(0.2, 0.3, 0.6), Color::new(0.7, 0.7, 0.75));
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, OrenNayar::textured(fabric, 60.0)));
+
+    let mut lights = LightList::new();
+    lights.add(DirectionalLight::new(Vec3::new(-1.0, -0.6, -0.4), 0.5, Emission::new(Color::new(1.0, 1.0, 1.0), 3.0)));
+
+    (camera, world, lights)
+}
+
+// Marble, skin, wax and milk spheres lit from behind, where light bleeding through the edges shows the scattering
+pub fn subsurface() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 11.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Gradient::new(Color::new(0.1, 0.1, 0.12), Color::new(0.02, 0.02, 0.03));
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.2, 0.2), Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    // The walks inside are traced against a copy of each sphere
+    let shape = |x: f32| Sphere::new(Point::new(x, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+
+    let marble = Subsurface::new(shape(-3.3), Color::new(0.99, 0.99, 0.98), Color::new(0.3, 0.3, 0.25), 1.5);
+    world.add(Sphere::new(Point::new(-3.3, 1.0, 0.0), 1.0, marble));
+
+    let skin = Subsurface::new(shape(-1.1), Color::new(0.97, 0.85, 0.75), Color::new(0.4, 0.15, 0.08), 1.4);
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, skin));
+
+    let wax = Subsurface::new(shape(1.1), Color::new(0.99, 0.95, 0.8), Color::new(0.5, 0.4, 0.2), 1.45);
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, wax));
+
+    let milk = Subsurface::new(shape(3.3), Color::new(0.999, 0.999, 0.997), Color::new(0.08, 0.07, 0.05), 1.35);
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, milk));
+
+    let mut lights = LightList::new();
+    lights.add(DirectionalLight::new(Vec3::new(0.3, -0.5, 1.0), 0.5, Emission::new(Color::new(1.0, 1.0, 1.0), 3.0)));
+    lights.add(PointLight::new(Point::new(-2.0, 5.0, 6.0), 0.5, Emission::new(Color::new(1.0, 1.0, 1.0), 40.0)));
+
+    (camera, world, lights)
+}
+
+// Smoke and a forward scattering cloud, with a spotlight shining through a thin haze that fills the scene.
+// Spheres further back fade into the optional atmospheric fog.
+pub fn volumes(fog_density: f32) -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 11.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.max_depth = 32;
+    camera.environment = Gradient::new(Color::new(0.02, 0.02, 0.03), Color::new(0.05, 0.06, 0.1));
+    if fog_density > 0.0 {
+        camera.fog = Some(Fog::new(Color::new(0.05, 0.06, 0.08), fog_density));
+    }
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.2, 0.2), Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    // Only the shape of the boundary matters, not its material
+    let smoke = Sphere::new(Point::new(-2.2, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+    world.add(ConstantMedium::new(smoke, 2.0, Phase::isotropic(Color::new(0.7, 0.7, 0.7))));
+
+    let cloud = Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+    world.add(ConstantMedium::new(cloud, 6.0, Phase::henyey_greenstein(Color::new(0.95, 0.95, 0.95), 0.7)));
+
+    world.add(Sphere::new(Point::new(2.2, 1.0, 0.0), 1.0, Lambertian::new(Color::new(0.7, 0.1, 0.1))));
+    for i in 1..=4 {
+        let z = -4.0 * i as f32;
+        world.add(Sphere::new(Point::new(-4.0, 1.0, z), 1.0, Lambertian::new(Color::new(0.2, 0.4, 0.7))));
+        world.add(Sphere::new(Point::new(4.0, 1.0, z), 1.0, Lambertian::new(Color::new(0.2, 0.4, 0.7))));
+    }
+
+    // The camera sits inside the haze as well
+    let haze = Sphere::new(Point::new(0.0, 0.0, 0.0), 30.0, Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+    world.add(ConstantMedium::new(haze, 0.03, Phase::henyey_greenstein(Color::new(1.0, 1.0, 1.0), 0.5)));
+
+    let mut lights = LightList::new();
+    let spot = Emission::new(Color::new(1.0, 0.9, 0.7), 200.0);
+    lights.add(SpotLight::new(Point::new(-3.0, 7.0, 2.0), Vec3::new(0.4, -1.0, -0.2), 15.0, 25.0, 0.1, spot));
+
+    (camera, world, lights)
+}
+
+// A cloud and a fire built from procedural density grids, or a grid loaded from a raw file or a directory
+// of slices in the middle
+pub fn clouds(grid_path: Option<&str>) -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 11.0), Point::new(0.0, 1.2, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.max_depth = 32;
+    camera.environment = Sky::new(25.0, 140.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.2, 0.2), Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let perlin = Perlin::new(&mut RandomGenerator::seeded(5));
+
+    // Billowy noise inside a sphere that thins out towards its edge
+    let cloud = match grid_path {
+        Some(path) if std::path::Path::new(path).is_dir() => DensityGrid::load_slices(path),
+        Some(path) => DensityGrid::load_raw(path).map_err(image::ImageError::from),
+        None => Ok(DensityGrid::from_fn([64, 64, 64], |p| {
+            let r = (*p - Point::new(0.5, 0.5, 0.5)).length() * 2.0;
+            let noise = perlin.fbm(&(*p * 4.0), 5, 2.0, 0.5);
+            [(1.0 - r + 0.6 * noise).clamp(0.0, 1.0) * 2.0, 0.0, 0.0]
+        })),
+    }.unwrap_or_else(|err| panic!("Unable to load density grid {}: {}", grid_path.unwrap_or_default(), err));
+    let medium = GridMedium::new(cloud, Point::new(-1.2, 0.2, -1.2), Point::new(1.2, 2.6, 1.2), 10.0, Phase::henyey_greenstein(Color::new(0.95, 0.95, 0.95), 0.6));
+    world.add(medium);
+
+    // Hot in the core near the bottom, cooling and turning into smoke as it rises
+    let fire = DensityGrid::from_fn([48, 64, 48], |p| {
+        let radius = 0.45 * (1.0 - p.y).max(0.0) * (1.0 + 0.4 * perlin.fbm(&(*p * 6.0), 4, 2.0, 0.5));
+        let r = ((p.x - 0.5).powi(2) + (p.z - 0.5).powi(2)).sqrt();
+        let inside = ((radius - r) / 0.1).clamp(0.0, 1.0);
+        let heat = inside * (1.0 - p.y).powi(2);
+        [inside * 0.6, heat, (heat - 0.6).max(0.0)]
+    });
+    let glow = Glow { emission: Color::new(1.0, 0.9, 0.6) * 4.0, kelvin_per_unit: 2000.0, blackbody_intensity: 6.0 };
+    let flames = GridMedium::glowing(fire, Point::new(2.4, 0.0, -0.6), Point::new(3.6, 2.2, 0.6), 5.0, Phase::isotropic(Color::new(0.2, 0.2, 0.2)), glow);
+    world.add(flames);
+
+    world.add(Sphere::new(Point::new(-3.0, 0.8, 0.0), 0.8, Lambertian::new(Color::new(0.2, 0.4, 0.7))));
+
+    (camera, world, LightList::new())
+}
+
+// Dielectrics inside each other: plain water, ice poking out of water that nearly vanishes below the
+// surface, an air bubble in water, and a glass ball with an amber core
+pub fn nested() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 11.0), Point::new(0.0, 0.8, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.max_depth = 32;
+    camera.environment = Sky::new(30.0, 200.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.2, 0.2), Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let water = || Dielectric::new(Color::new(0.85, 0.95, 1.0), 1.33).with_absorption_distance(2.0).with_priority(1);
+
+    world.add(Sphere::new(Point::new(-3.3, 1.0, 0.0), 1.0, water()));
+
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, water()));
+    world.add(Sphere::new(Point::new(-1.1, 1.7, 0.3), 0.5, Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.31).with_priority(2)));
+
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, water()));
+    world.add(Sphere::new(Point::new(1.1, 1.2, 0.3), 0.45, Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.0).with_priority(2)));
+
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.5).with_priority(1)));
+    let amber = Dielectric::new(Color::new(0.9, 0.5, 0.1), 1.36).with_absorption_distance(0.5).with_priority(2);
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 0.7, amber));
+
+    (camera, world, LightList::new())
+}
+
+// A soap bubble, a coated lens, heat-tinted steel and an oil-slicked plastic
+pub fn iridescence() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 2.0, 13.0), Point::new(0.0, 1.0, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(40.0, 150.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.1, 0.1, 0.1), Color::new(0.5, 0.5, 0.5));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let swirl = NoiseTexture::new(&mut RandomGenerator::seeded(11), NoiseKind::Turbulence, 0.6, 4, Color::new(1.0, 1.0, 1.0));
+    let bubble = Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.33).thin_walled().with_thin_film(ThinFilm::textured(swirl, 200.0, 900.0, 1.33));
+    world.add(Sphere::new(Point::new(-3.3, 1.2, 0.0), 1.2, bubble));
+
+    let lens = Dielectric::new(Color::new(1.0, 1.0, 1.0), 1.5).with_thin_film(ThinFilm::new(100.0, 1.38));
+    world.add(Sphere::new(Point::new(-1.1, 1.0, 0.0), 1.0, lens));
+
+    let steel = Metal::new(Color::new(0.6, 0.6, 0.6), 0.05).with_thin_film(ThinFilm::new(350.0, 2.2));
+    world.add(Sphere::new(Point::new(1.1, 1.0, 0.0), 1.0, steel));
+
+    let oil = NoiseTexture::new(&mut RandomGenerator::seeded(3), NoiseKind::Fbm, 1.0, 5, Color::new(1.0, 1.0, 1.0));
+    let slick = PrincipledParams {
+        base_color: SolidColor::new(Color::new(0.02, 0.02, 0.02)),
+        roughness: SolidColor::scalar(0.05),
+        thin_film: Some(ThinFilm::textured(oil, 250.0, 700.0, 2.0)),
+        ..Default::default()
+    };
+    world.add(Sphere::new(Point::new(3.3, 1.0, 0.0), 1.0, Principled::new(slick)));
+
+    (camera, world, LightList::new())
+}
+
+// Rust patches on steel, a half-and-half blend and a cut-out cage casting a patterned shadow
+pub fn mix() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 3.0, 12.0), Point::new(0.0, 1.0, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(50.0, 140.0, 3.0, 0.02);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.25, 0.25, 0.25), Color::new(0.6, 0.6, 0.6));
+    world.add(Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::textured(ground)));
+
+    let patches = NoiseTexture::new(&mut RandomGenerator::seeded(7), NoiseKind::Turbulence, 1.5, 5, Color::new(2.5, 2.5, 2.5));
+    let steel = Metal::new(Color::new(0.7, 0.7, 0.72), 0.1);
+    let rust = Lambertian::new(Color::new(0.45, 0.18, 0.06));
+    world.add(Sphere::new(Point::new(-2.6, 1.0, 0.0), 1.0, MixMaterial::textured(steel, rust, patches)));
+
+    let blend = MixMaterial::new(Lambertian::new(Color::new(0.1, 0.3, 0.6)), OrenNayar::new(Color::new(0.8, 0.8, 0.7), 20.0), 0.5);
+    world.add(Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, blend));
+
+    let bars = Checker::from_colors(0.2, Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0));
+    let cage = AlphaMask::new(Lambertian::new(Color::new(0.8, 0.6, 0.2)), bars);
+    world.add(Sphere::new(Point::new(2.6, 1.0, 0.0), 1.0, cage));
+    world.add(Sphere::new(Point::new(2.6, 1.0, 0.0), 0.4, Lambertian::new(Color::new(0.7, 0.1, 0.1))));
+
+    let mut lights = LightList::new();
+    lights.add(DirectionalLight::new(Vec3::new(-0.5, -1.0, -0.6), 0.3, Emission::new(Color::new(1.0, 0.95, 0.9), 2.0)));
+
+    (camera, world, lights)
+}
+
+// Cornell box from quads, with a white and a glass box inside
+pub fn cornell() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(38.0, Point::new(1.0, 1.0, 4.8), Point::new(1.0, 1.0, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Gradient::new(Color::new(0.0, 0.0, 0.0), Color::new(0.0, 0.0, 0.0));
+
+    let mut world = HittableList::new();
+
+    let red = Lambertian::new(Color::new(0.65, 0.05, 0.05));
+    let white = Lambertian::new(Color::new(0.73, 0.73, 0.73));
+    let green = Lambertian::new(Color::new(0.12, 0.45, 0.15));
+
+    let x = Vec3::new(2.0, 0.0, 0.0);
+    let y = Vec3::new(0.0, 2.0, 0.0);
+    let z = Vec3::new(0.0, 0.0, 2.0);
+    world.add(Quad::new(Point::new(0.0, 0.0, 0.0), z, y, red));
+    world.add(Quad::new(Point::new(2.0, 0.0, 0.0), y, z, green));
+    world.add(Quad::new(Point::new(0.0, 0.0, 0.0), x, y, white.clone()));
+    world.add(Quad::new(Point::new(0.0, 0.0, 0.0), z, x, white.clone()));
+    world.add(Quad::new(Point::new(0.0, 2.0, 0.0), x, z, white.clone()));
+
+    world.add(make_box(Point::new(0.3, 0.0, 0.4), Point::new(0.9, 1.2, 1.0), white));
+    world.add(make_box(Point::new(1.1, 0.0, 1.0), Point::new(1.7, 0.6, 1.6), Dielectric::new(Color::new(0.9, 0.95, 1.0), 1.5)));
+
+    let mut lights = LightList::new();
+    lights.add(SpotLight::new(Point::new(1.0, 1.95, 1.0), Vec3::new(0.0, -1.0, 0.0), 60.0, 80.0, 0.2, Emission::new(Color::new(1.0, 0.9, 0.8), 6.0)));
+
+    (camera, world, lights)
+}
+
+// Cylinders, a pipe, a cone, disks and tori standing on an infinite plane
+pub fn quadrics() -> (Camera, HittableList, LightList) {
+
+    let mut camera = Camera::initialize(30.0, Point::new(0.0, 4.0, 13.0), Point::new(0.0, 0.9, 0.0));
+    camera.defocus_angle = 0.0;
+    camera.environment = Sky::new(45.0, 130.0, 3.0, 0.03);
+
+    let mut world = HittableList::new();
+
+    let ground = Checker::from_colors(0.5, Color::new(0.2, 0.2, 0.2), Color::new(0.6, 0.6, 0.6));
+    world.add(Plane::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Lambertian::textured(ground)));
+
+    let brushed = NoiseTexture::new(&mut RandomGenerator::seeded(2), NoiseKind::Turbulence, 8.0, 3, Color::new(1.0, 1.0, 1.0));
+    let steel = BumpMap::new(Metal::new(Color::new(0.75, 0.75, 0.78), 0.05), brushed, 0.004);
+    world.add(Cylinder::new(Point::new(-4.2, 0.0, 0.0), Point::new(-4.2, 2.0, 0.0), 0.7, steel));
+
+    let pipe = Lambertian::new(Color::new(0.8, 0.35, 0.1));
+    world.add(Cylinder::open(Point::new(-2.2, 0.5, -0.9), Point::new(-2.2, 0.5, 0.9), 0.5, pipe));
+
+    world.add(Cone::new(Point::new(0.0, 0.0, 0.0), Point::new(0.0, 2.2, 0.0), 0.9, Lambertian::new(Color::new(0.1, 0.3, 0.7))));
+    world.add(Cone::open(Point::new(0.0, 2.6, 0.0), Point::new(0.0, 2.2, 0.0), 0.5, Metal::new(Color::new(0.9, 0.9, 0.9), 0.0)));
+
+    let gold = Metal::new(Color::new(1.0, 0.78, 0.34), 0.1);
+    world.add(Torus::new(Point::new(2.2, 0.35, 0.4), Vec3::new(0.0, 1.0, 0.0), 0.8, 0.35, gold));
+    world.add(Torus::new(Point::new(4.3, 1.05, 0.0), Vec3::new(0.3, 0.0, 1.0), 0.75, 0.3, Dielectric::new(Color::new(0.9, 1.0, 0.95), 1.5)));
+
+    world.add(Disk::annulus(Point::new(-1.0, 1.3, -3.0), Vec3::new(0.0, 0.0, 1.0), 0.5, 1.3, Lambertian::new(Color::new(0.7, 0.7, 0.2))));
+    world.add(Disk::new(Point::new(2.8, 2.6, -2.5), Vec3::new(-0.3, 0.2, 1.0), 0.9, Metal::new(Color::new(0.95, 0.95, 0.95), 0.0)));
+
+    (camera, world, LightList::new())
+}
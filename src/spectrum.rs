@@ -0,0 +1,358 @@
+use std::sync::OnceLock;
+
+use crate::util::{xyz_to_linear_srgb, Vec3};
+
+use Vec3 as Color;
+
+// Visible range used when integrating spectra, in nanometers
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+
+// CIE 1931 standard observer colour matching functions, using the multi-lobe fit from
+// Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn cie_xyz(lambda: f32) -> (f32, f32, f32) {
+    let g = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let t = (lambda - mu) / if lambda < mu { sigma_low } else { sigma_high };
+        (-0.5 * t * t).exp()
+    };
+
+    let x = 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// Planck's law for a black body at `kelvin`, with the wavelength in nanometers. Units are arbitrary.
+pub fn planck(lambda: f32, kelvin: f32) -> f32 {
+    const C2: f64 = 1.4387769e7; // Second radiation constant in nm K
+
+    let lambda = lambda as f64;
+    let value = 1.0 / (lambda.powi(5) * ((C2 / (lambda * kelvin as f64)).exp() - 1.0));
+    (value * 1e15) as f32
+}
+
+// Picks a wavelength uniformly over the visible range, for paths that split into a single wavelength
+pub fn sample_wavelength(u: f32) -> f32 {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+// Linear sRGB weight of a path that was split into a single, uniformly sampled wavelength, scaled so
+// that averaging over all wavelengths gives white. It lands the path back in RGB.
+pub fn wavelength_weight(lambda: f32) -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let (x, y, z) = cie_xyz(lambda);
+            sum = sum + xyz_to_linear_srgb(x, y, z);
+            lambda += 1.0;
+        }
+        sum / (LAMBDA_MAX - LAMBDA_MIN + 1.0)
+    });
+
+    let (x, y, z) = cie_xyz(lambda);
+    let rgb = xyz_to_linear_srgb(x, y, z);
+    Color::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+}
+
+// Linear sRGB colour of a reflectance spectrum, so that a flat spectrum of 1 gives white. For spectra that
+// wiggle too much to up-sample from RGB, like thin film interference.
+pub fn spectrum_to_rgb(reflectance: impl Fn(f32) -> f32) -> Color {
+    const SAMPLES: usize = 32;
+    const LOW: f32 = 380.0;
+    const HIGH: f32 = 780.0;
+    let lambda = |i: usize| LOW + (i as f32 + 0.5) * (HIGH - LOW) / SAMPLES as f32;
+
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    let white = WHITE.get_or_init(|| (0..SAMPLES).fold(Color::new(0.0, 0.0, 0.0), |sum, i| sum + wavelength_weight(lambda(i))));
+
+    let sum = (0..SAMPLES).fold(Color::new(0.0, 0.0, 0.0), |sum, i| sum + wavelength_weight(lambda(i)) * reflectance(lambda(i)));
+    sum / *white
+}
+
+// Integral of the CIE y curve over the visible range. A flat spectrum of 1 has unit luminance.
+fn y_integral() -> f32 {
+    static INTEGRAL: OnceLock<f32> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| {
+        let mut sum = 0.0;
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            sum += cie_xyz(lambda).1;
+            lambda += 1.0;
+        }
+        sum
+    })
+}
+
+// Weights of the red, green and blue basis spectra at a wavelength. They sum to one everywhere,
+// so white up-samples to a flat spectrum and reflectances stay below one.
+fn rgb_basis(lambda: f32) -> Color {
+    let step = |edge: f32| {
+        let t = ((lambda - edge) / 60.0 + 0.5).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+
+    let blue_to_green = step(490.0);
+    let green_to_red = step(585.0);
+    Color::new(green_to_red, blue_to_green - green_to_red, 1.0 - blue_to_green)
+}
+
+// Value of a smooth spectrum for an RGB reflectance (or any other RGB quantity) at one wavelength
+pub fn upsample_rgb(rgb: &Color, lambda: f32) -> f32 {
+    Vec3::dot(rgb, &rgb_basis(lambda))
+}
+
+// Emission spectrum tabulated over wavelength, scaled to unit luminance
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    samples: Vec<(f32, f32)>, // (wavelength in nm, value), sorted by wavelength
+    color: Color,             // Linear sRGB colour of the spectrum for the RGB pipeline
+}
+
+impl Spectrum {
+    pub fn tabulated(mut samples: Vec<(f32, f32)>) -> Self {
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut spectrum = Spectrum { samples, color: Color::new(0.0, 0.0, 0.0) };
+
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let value = spectrum.value(lambda);
+            let (xb, yb, zb) = cie_xyz(lambda);
+            x += xb * value;
+            y += yb * value;
+            z += zb * value;
+            lambda += 1.0;
+        }
+
+        if y > 0.0 {
+            let scale = y_integral() / y;
+            spectrum.samples.iter_mut().for_each(|sample| sample.1 *= scale);
+            spectrum.color = xyz_to_linear_srgb(x / y, 1.0, z / y);
+        }
+        spectrum
+    }
+
+    pub fn blackbody(kelvin: f32) -> Self {
+        let samples = (0..=((LAMBDA_MAX - LAMBDA_MIN) / 5.0) as u32)
+            .map(|i| {
+                let lambda = LAMBDA_MIN + i as f32 * 5.0;
+                (lambda, planck(lambda, kelvin))
+            })
+            .collect();
+        Spectrum::tabulated(samples)
+    }
+
+    // Stand-in for the D65 daylight illuminant that white light in the RGB pipeline corresponds to
+    pub fn daylight() -> &'static Self {
+        static DAYLIGHT: OnceLock<Spectrum> = OnceLock::new();
+        DAYLIGHT.get_or_init(|| Spectrum::blackbody(6504.0))
+    }
+
+    // Linear interpolation between samples, and zero outside of them
+    pub fn value(&self, lambda: f32) -> f32 {
+        let i = self.samples.partition_point(|sample| sample.0 < lambda);
+        if i == 0 || i == self.samples.len() {
+            return match self.samples.get(i) {
+                Some(&(l, v)) if l == lambda => v,
+                _ => 0.0,
+            };
+        }
+
+        let (l0, v0) = self.samples[i - 1];
+        let (l1, v1) = self.samples[i];
+        v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+}
+
+// The wavelengths a camera path carries in spectral mode: a uniformly sampled hero wavelength and two more
+// spaced evenly after it, wrapping around the visible range. Colors along such a path hold one value per
+// wavelength instead of red, green and blue.
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    lambda: [f32; 3],
+}
+
+impl Wavelengths {
+    pub fn sample(u: f32) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = sample_wavelength(u);
+        let lambda = [0.0, 1.0, 2.0].map(|i| {
+            let l = hero + i * range / 3.0;
+            if l > LAMBDA_MAX { l - range } else { l }
+        });
+        Wavelengths { lambda }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    pub fn map(&self, f: impl Fn(f32) -> f32) -> Color {
+        Color::new(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+
+    // Reflectances and other unitless RGB values
+    pub fn upsample(&self, rgb: &Color) -> Color {
+        self.map(|lambda| upsample_rgb(rgb, lambda))
+    }
+
+    // RGB light, so that white turns into daylight
+    pub fn upsample_illuminant(&self, rgb: &Color) -> Color {
+        let daylight = Spectrum::daylight();
+        &self.upsample(rgb) * &self.map(|lambda| daylight.value(lambda))
+    }
+
+    pub fn spectrum(&self, spectrum: &Spectrum) -> Color {
+        self.map(|lambda| spectrum.value(lambda))
+    }
+
+    // Monte Carlo estimate of CIE XYZ from radiance at these wavelengths
+    pub fn to_xyz(self, radiance: &Color) -> Color {
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / (3.0 * y_integral());
+        let values = [radiance.x, radiance.y, radiance.z];
+
+        let mut xyz = Color::new(0.0, 0.0, 0.0);
+        for (lambda, value) in self.lambda.iter().zip(values) {
+            let (x, y, z) = cie_xyz(*lambda);
+            xyz = xyz + Color::new(x, y, z) * (value * scale);
+        }
+        xyz
+    }
+}
+
+// Wavelength dependent index of refraction. Coefficients use wavelengths in micrometers,
+// as they are usually published.
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    Cauchy { a: f32, b: f32 },
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier { b: [1.039_612, 0.231_792_34, 1.010_469_5], c: [0.006_000_699, 0.020_017_914, 103.560_65] }
+    }
+
+    pub fn dense_flint() -> Self {
+        Dispersion::Sellmeier { b: [1.737_597, 0.313_747_35, 1.898_781], c: [0.013_188_707, 0.062_306_814, 155.236_3] }
+    }
+
+    pub fn diamond() -> Self {
+        Dispersion::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.030_625, 0.011_236, 0.0] }
+    }
+
+    pub fn index(&self, lambda: f32) -> f32 {
+        let l2 = (lambda * 1e-3).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f32 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Catalogue indices at the Fraunhofer F, d and C lines
+    #[test]
+    fn sellmeier_matches_catalogue() {
+        let bk7 = Dispersion::bk7();
+        assert!((bk7.index(486.1) - 1.5224).abs() < 2e-4);
+        assert!((bk7.index(587.6) - 1.5168).abs() < 2e-4);
+        assert!((bk7.index(656.3) - 1.5143).abs() < 2e-4);
+
+        assert!((Dispersion::dense_flint().index(587.6) - 1.7847).abs() < 2e-4);
+        assert!((Dispersion::diamond().index(587.6) - 2.4175).abs() < 2e-3);
+    }
+
+    #[test]
+    fn cauchy_uses_micrometers() {
+        let glass = Dispersion::Cauchy { a: 1.5, b: 0.0042 };
+        assert!((glass.index(500.0) - 1.5168).abs() < 1e-5);
+    }
+
+    // Blue bends more than red in all of them
+    #[test]
+    fn index_falls_with_wavelength() {
+        for dispersion in [Dispersion::bk7(), Dispersion::dense_flint(), Dispersion::diamond(), Dispersion::Cauchy { a: 1.5, b: 0.0042 }] {
+            let mut last = f32::INFINITY;
+            let mut lambda = 400.0;
+            while lambda <= 700.0 {
+                let index = dispersion.index(lambda);
+                assert!(index < last, "{dispersion:?} at {lambda}");
+                last = index;
+                lambda += 10.0;
+            }
+        }
+    }
+
+    // Averaged over all wavelengths, the weights of a split path come back to white
+    #[test]
+    fn sampled_wavelengths_average_to_white() {
+        let n = 4_000;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let lambda = sample_wavelength((i as f32 + 0.5) / n as f32);
+            assert!((LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda));
+            sum = sum + wavelength_weight(lambda);
+        }
+        let average = sum / n as f32;
+        for channel in [average.x, average.y, average.z] {
+            assert!((channel - 1.0).abs() < 1e-2, "{average:?}");
+        }
+    }
+
+    #[test]
+    fn wavelengths_are_evenly_spaced() {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        for u in [0.0, 0.3, 0.7, 0.999] {
+            let wavelengths = Wavelengths::sample(u);
+            assert_eq!(wavelengths.hero(), sample_wavelength(u));
+            for (i, lambda) in wavelengths.lambda.iter().enumerate() {
+                assert!((LAMBDA_MIN..=LAMBDA_MAX).contains(lambda));
+                let offset = (lambda - wavelengths.hero()).rem_euclid(range);
+                assert!((offset - i as f32 * range / 3.0).abs() < 1e-3);
+            }
+        }
+    }
+
+    // White up-samples to a flat spectrum, and a flat spectrum of 1 averages to unit luminance
+    // with the chromaticity of equal energy white
+    #[test]
+    fn flat_spectrum_averages_to_unit_luminance() {
+        let n = 3_000;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let wavelengths = Wavelengths::sample((i as f32 + 0.5) / n as f32);
+            let flat = wavelengths.upsample(&Color::new(1.0, 1.0, 1.0));
+            assert!((flat - Color::new(1.0, 1.0, 1.0)).length() < 1e-5);
+            sum = sum + wavelengths.to_xyz(&flat);
+        }
+
+        let xyz = sum / n as f32;
+        assert!((xyz.y - 1.0).abs() < 1e-3, "{xyz:?}");
+        assert!((xyz.x - 1.0).abs() < 0.03 && (xyz.z - 1.0).abs() < 0.03, "{xyz:?}");
+    }
+
+    #[test]
+    fn rgb_basis_is_a_partition_of_unity() {
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let basis = rgb_basis(lambda);
+            assert!(basis.x >= 0.0 && basis.y >= 0.0 && basis.z >= 0.0);
+            assert!((basis.x + basis.y + basis.z - 1.0).abs() < 1e-6);
+            lambda += 5.0;
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::noise::{Perlin, Worley};
+use crate::random::RandomGenerator;
+use crate::util::Vec3;
+
+use Vec3 as Point;
+use Vec3 as Color;
+
+// Spatially varying material parameters, looked up by surface coordinates and hit point.
+// Scalar parameters such as fuzz read the first (red) channel.
+pub trait Texture: Send + Sync + Debug {
+    fn value(&self, u: f32, v: f32, point: &Point) -> Color;
+
+    // Averages the texture over a pixel's footprint in uv space. Only textures
+    // that can alias (images) need to override this.
+    fn filtered_value(&self, u: f32, v: f32, point: &Point, _derivatives: &UvDerivatives) -> Color {
+        self.value(u, v, point)
+    }
+}
+
+// Change in texture coordinates from one pixel to the next, zero when unknown
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UvDerivatives {
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
+}
+
+
+// ----------------------------------------------
+// Solid color ----------------------------------
+// ----------------------------------------------
+#[derive(Debug)]
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Arc<Self> {
+        Arc::new(SolidColor { albedo })
+    }
+
+    pub fn scalar(value: f32) -> Arc<Self> {
+        SolidColor::new(Color::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _point: &Point) -> Color {
+        self.albedo
+    }
+}
+
+
+// ----------------------------------------------
+// 3D checker -----------------------------------
+// ----------------------------------------------
+#[derive(Debug)]
+pub struct Checker {
+    inv_scale: f32,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl Checker {
+    // `scale` is the edge length of one checker cell in world units
+    pub fn new(scale: f32, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Arc<Self> {
+        Arc::new(Checker { inv_scale: 1.0 / scale, even, odd })
+    }
+
+    pub fn from_colors(scale: f32, even: Color, odd: Color) -> Arc<Self> {
+        Checker::new(scale, SolidColor::new(even), SolidColor::new(odd))
+    }
+}
+
+impl Checker {
+    fn pick(&self, point: &Point) -> &Arc<dyn Texture> {
+        let x = (self.inv_scale * point.x).floor() as i32;
+        let y = (self.inv_scale * point.y).floor() as i32;
+        let z = (self.inv_scale * point.z).floor() as i32;
+
+        if (x + y + z) % 2 == 0 { &self.even } else { &self.odd }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, point: &Point) -> Color {
+        self.pick(point).value(u, v, point)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, point: &Point, derivatives: &UvDerivatives) -> Color {
+        self.pick(point).filtered_value(u, v, point, derivatives)
+    }
+}
+
+
+// ----------------------------------------------
+// Image texture --------------------------------
+// ----------------------------------------------
+// How texture coordinates outside [0, 1] are handled
+#[derive(Debug, Clone, Copy)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Debug)]
+pub struct ImageTexture {
+    levels: Vec<MipLevel>, // Mipmap pyramid, from full resolution down to a single texel
+    wrap: WrapMode,
+}
+
+#[derive(Debug)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    // 8-bit color images are usually sRGB encoded and need decoding to linear, while data
+    // such as normal maps or roughness should pass `srgb = false`. Float images are always linear.
+    pub fn load(path: &str, wrap: WrapMode, srgb: bool) -> Result<Arc<Self>, image::ImageError> {
+        let img = image::open(path)?;
+        let decode = srgb && !matches!(img.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F);
+
+        let img = img.into_rgb32f();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let pixels = img.pixels()
+            .map(|px| {
+                let color = Color::new(px[0], px[1], px[2]);
+                if decode { Color::new(srgb_to_linear(color.x), srgb_to_linear(color.y), srgb_to_linear(color.z)) } else { color }
+            })
+            .collect();
+
+        let mut levels = vec![MipLevel { width, height, pixels }];
+        while let Some(next) = levels.last().unwrap().downsample() {
+            levels.push(next);
+        }
+
+        Ok(Arc::new(ImageTexture { levels, wrap }))
+    }
+
+    // Bilinear lookup within a single mip level
+    fn bilinear(&self, level: usize, u: f32, v: f32) -> Color {
+        let level = &self.levels[level];
+
+        // Image rows go top to bottom, while v goes up
+        let x = u * level.width as f32 - 0.5;
+        let y = (1.0 - v) * level.height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let texel = |dx: i64, dy: i64| {
+            let tx = wrap_index(x0 as i64 + dx, level.width, self.wrap);
+            let ty = wrap_index(y0 as i64 + dy, level.height, self.wrap);
+            level.pixels[ty * level.width + tx]
+        };
+
+        let top = lerp(&texel(0, 0), &texel(1, 0), fx);
+        let bottom = lerp(&texel(0, 1), &texel(1, 1), fx);
+        lerp(&top, &bottom, fy)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _point: &Point) -> Color {
+        if self.levels[0].pixels.is_empty() {
+            return Color::new(0.0, 1.0, 1.0); // Solid cyan makes a missing texture obvious
+        }
+
+        self.bilinear(0, u, v)
+    }
+
+    // Trilinear filtering: pick the two mip levels whose texels best match the footprint and blend them
+    fn filtered_value(&self, u: f32, v: f32, point: &Point, derivatives: &UvDerivatives) -> Color {
+        if self.levels[0].pixels.is_empty() {
+            return self.value(u, v, point);
+        }
+
+        let width = self.levels[0].width as f32;
+        let height = self.levels[0].height as f32;
+        let footprint = (derivatives.dudx * width).abs()
+            .max((derivatives.dvdx * height).abs())
+            .max((derivatives.dudy * width).abs())
+            .max((derivatives.dvdy * height).abs());
+
+        if footprint <= 1.0 {
+            return self.bilinear(0, u, v);
+        }
+
+        let lod = footprint.log2().min((self.levels.len() - 1) as f32);
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+
+        lerp(&self.bilinear(lower, u, v), &self.bilinear(upper, u, v), lod - lower as f32)
+    }
+}
+
+impl MipLevel {
+    // Box filters down to half resolution, or None once a single texel is left
+    fn downsample(&self) -> Option<MipLevel> {
+        if self.width <= 1 && self.height <= 1 {
+            return None;
+        }
+
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = (2 * x).min(self.width - 1);
+                let x1 = (2 * x + 1).min(self.width - 1);
+                let y0 = (2 * y).min(self.height - 1);
+                let y1 = (2 * y + 1).min(self.height - 1);
+
+                let sum = self.pixels[y0 * self.width + x0]
+                    + self.pixels[y0 * self.width + x1]
+                    + self.pixels[y1 * self.width + x0]
+                    + self.pixels[y1 * self.width + x1];
+                pixels.push(sum * 0.25);
+            }
+        }
+
+        Some(MipLevel { width, height, pixels })
+    }
+}
+
+fn wrap_index(i: i64, size: usize, wrap: WrapMode) -> usize {
+    let n = size as i64;
+    let wrapped = match wrap {
+        WrapMode::Repeat => i.rem_euclid(n),
+        WrapMode::Clamp => i.clamp(0, n - 1),
+        WrapMode::Mirror => {
+            let period = i.rem_euclid(2 * n);
+            if period < n { period } else { 2 * n - 1 - period }
+        }
+    };
+    wrapped as usize
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// ----------------------------------------------
+// Procedural noise -----------------------------
+// ----------------------------------------------
+#[derive(Debug, Clone, Copy)]
+pub enum NoiseKind {
+    Perlin,     // Single octave of smooth noise
+    Turbulence, // Absolute sum of octaves
+    Fbm,        // Signed sum of octaves (fractional Brownian motion)
+}
+
+#[derive(Debug)]
+pub struct NoiseTexture {
+    perlin: Perlin,
+    kind: NoiseKind,
+    scale: f32,
+    octaves: u32,
+    color: Color,
+}
+
+impl NoiseTexture {
+    pub fn new(rand: &mut RandomGenerator, kind: NoiseKind, scale: f32, octaves: u32, color: Color) -> Arc<Self> {
+        Arc::new(NoiseTexture { perlin: Perlin::new(rand), kind, scale, octaves: octaves.max(1), color })
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Point) -> Color {
+        let p = *point * self.scale;
+        let value = match self.kind {
+            NoiseKind::Perlin => 0.5 * (1.0 + self.perlin.noise(&p)),
+            NoiseKind::Turbulence => self.perlin.turbulence(&p, self.octaves),
+            NoiseKind::Fbm => 0.5 * (1.0 + self.perlin.fbm(&p, self.octaves, 2.0, 0.5)),
+        };
+
+        self.color * value.clamp(0.0, 1.0)
+    }
+}
+
+// Veins along the z-axis, phase shifted by turbulence
+#[derive(Debug)]
+pub struct Marble {
+    perlin: Perlin,
+    scale: f32,
+    base: Color,
+    vein: Color,
+}
+
+impl Marble {
+    pub fn new(rand: &mut RandomGenerator, scale: f32, base: Color, vein: Color) -> Arc<Self> {
+        Arc::new(Marble { perlin: Perlin::new(rand), scale, base, vein })
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f32, _v: f32, point: &Point) -> Color {
+        let t = 0.5 * (1.0 + (self.scale * point.z + 10.0 * self.perlin.turbulence(point, 7)).sin());
+        lerp(&self.vein, &self.base, t)
+    }
+}
+
+// Growth rings around the y-axis, distorted by fBm
+#[derive(Debug)]
+pub struct Wood {
+    perlin: Perlin,
+    rings: f32, // Rings per world unit
+    light: Color,
+    dark: Color,
+}
+
+impl Wood {
+    pub fn new(rand: &mut RandomGenerator, rings: f32, light: Color, dark: Color) -> Arc<Self> {
+        Arc::new(Wood { perlin: Perlin::new(rand), rings, light, dark })
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, _u: f32, _v: f32, point: &Point) -> Color {
+        let distortion = 0.3 * self.perlin.fbm(&(*point * 2.0), 4, 2.0, 0.5);
+        let radius = (point.x * point.x + point.z * point.z).sqrt() * self.rings + distortion;
+
+        // Sharpen the transition so the late wood forms thin dark bands
+        let ring = radius - radius.floor();
+        lerp(&self.light, &self.dark, ring.powi(3))
+    }
+}
+
+// Voronoi cells with their own brightness, fading to the edge color away from each feature point
+#[derive(Debug)]
+pub struct Voronoi {
+    worley: Worley,
+    scale: f32,
+    cell: Color,
+    edge: Color,
+}
+
+impl Voronoi {
+    pub fn new(rand: &mut RandomGenerator, scale: f32, cell: Color, edge: Color) -> Arc<Self> {
+        Arc::new(Voronoi { worley: Worley::new(rand), scale, cell, edge })
+    }
+}
+
+impl Texture for Voronoi {
+    fn value(&self, _u: f32, _v: f32, point: &Point) -> Color {
+        let (distance, id) = self.worley.cell(&(*point * self.scale));
+        let cell = self.cell * (0.5 + 0.5 * id);
+        lerp(&cell, &self.edge, distance.clamp(0.0, 1.0))
+    }
+}
+
+fn lerp(a: &Color, b: &Color, t: f32) -> Color {
+    *a * (1.0 - t) + *b * t
+}
@@ -1,6 +1,8 @@
 use std::ops;
 
-#[derive(Debug, Clone, Copy)]
+use crate::spectrum::Wavelengths;
+
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -49,7 +51,7 @@ impl Vec3 {
     pub fn refract(uv: &Vec3, n: &Vec3, ni_over_nt: f32) -> Vec3 {
         let cos_theta = Vec3::dot(&(*uv * -1.0), n).min(1.0);
         let r_out_prep = (*uv + (*n * cos_theta)) * ni_over_nt;
-        let r_out_parallel = *n * (1.0 - r_out_prep.length_squared()).abs().sqrt();
+        let r_out_parallel = *n * -(1.0 - r_out_prep.length_squared()).abs().sqrt();
         r_out_prep + r_out_parallel
     }
 
@@ -118,11 +120,29 @@ impl ops::Div<f32> for Vec3 {
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub differentials: Option<RayDifferentials>, // Neighbouring rays one pixel over, for texture filtering
+    pub wavelength: Option<f32>, // Set once a dispersive surface has split the path into a single wavelength
+    pub wavelengths: Option<Wavelengths>, // Wavelengths of the path in spectral mode
+    pub specular: bool, // Set by materials that sampled it from a delta lobe, when their `eval` covers other lobes
+    pub media: Option<MediumStack>, // Set by dielectrics the path has crossed into or out of
+}
+
+// Offset rays towards the next pixel in x and y, tracking how wide a pixel is at each hit
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferentials {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray { origin, direction, differentials: None, wavelength: None, wavelengths: None, specular: false, media: None }
+    }
+
+    pub fn with_differentials(origin: Vec3, direction: Vec3, differentials: Option<RayDifferentials>) -> Ray {
+        Ray { origin, direction, differentials, wavelength: None, wavelengths: None, specular: false, media: None }
     }
 
     pub fn at(&self, t: f32) -> Vec3 {
@@ -136,6 +156,116 @@ impl Ray {
     pub fn origin(&self) -> &Vec3 {
         &self.origin
     }
+
+    // Index of refraction of the dielectric medium the ray travels through, 1 outside of any
+    pub fn medium_index(&self) -> f32 {
+        self.media.as_ref().and_then(|media| media.current()).map_or(1.0, |medium| medium.refractive_index)
+    }
+}
+
+
+// ----------------------------------------------
+// Medium stack ---------------------------------
+// ----------------------------------------------
+// Dielectric media a path is inside of. Where media overlap, like liquid poured up against the wall of a
+// glass, the one with the highest priority is the one that's really there.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MediumStack {
+    media: [Medium; 4],
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Medium {
+    pub id: u32, // Identity of the dielectric the medium is inside of
+    pub priority: u32,
+    pub refractive_index: f32,
+    pub absorption: Vec3, // Beer-Lambert coefficient per unit of distance
+}
+
+impl Medium {
+    // Two objects can share an index and priority, and absorption can vary over a textured surface,
+    // so media are told apart by the dielectric they belong to
+    pub fn same(&self, other: &Medium) -> bool {
+        self.id == other.id
+    }
+
+    // Beer-Lambert transmittance over a path of the given length, which may be infinite
+    pub fn transmittance(&self, distance: f32) -> Vec3 {
+        let channel = |a: f32| if a > 0.0 { (-a * distance).exp() } else { 1.0 };
+        Vec3::new(channel(self.absorption.x), channel(self.absorption.y), channel(self.absorption.z))
+    }
+}
+
+impl MediumStack {
+    // Medium the path is travelling through: the highest priority one, or the latest entered on a tie
+    pub fn current(&self) -> Option<&Medium> {
+        self.media[..self.len].iter().max_by_key(|medium| medium.priority)
+    }
+
+    pub fn contains(&self, medium: &Medium) -> bool {
+        self.media[..self.len].iter().any(|m| m.same(medium))
+    }
+
+    // Paths nested deeper than the stack holds forget about the outermost medium
+    pub fn push(&mut self, medium: Medium) {
+        if self.len == self.media.len() {
+            self.media.rotate_left(1);
+            self.len -= 1;
+        }
+        self.media[self.len] = medium;
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, medium: &Medium) {
+        if let Some(i) = self.media[..self.len].iter().rposition(|m| m.same(medium)) {
+            self.media[i..self.len].rotate_left(1);
+            self.len -= 1;
+        }
+    }
+}
+
+
+// ----------------------------------------------
+// Orthonormal basis ----------------------------
+// ----------------------------------------------
+// Local frame for sampling and shading, with w as the up direction
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    // Any frame around the unit vector `w`, for when the rotation about it doesn't matter
+    // (Duff et al. 2017, "Building an Orthonormal Basis, Revisited")
+    pub fn new(w: &Vec3) -> Self {
+        let sign = 1.0_f32.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        let u = Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x);
+        let v = Vec3::new(b, sign + w.y * w.y * a, -w.y);
+        Onb { u, v, w: *w }
+    }
+
+    // Frame around the unit vector `w` with u along `tangent`, projected into the plane
+    pub fn with_tangent(w: &Vec3, tangent: &Vec3) -> Self {
+        let u = *tangent - *w * Vec3::dot(tangent, w);
+        if u.near_zero() {
+            return Onb::new(w);
+        }
+        let u = u.unit_vector();
+        Onb { u, v: Vec3::cross(w, &u), w: *w }
+    }
+
+    pub fn to_local(self, a: &Vec3) -> Vec3 {
+        Vec3::new(Vec3::dot(a, &self.u), Vec3::dot(a, &self.v), Vec3::dot(a, &self.w))
+    }
+
+    pub fn to_world(self, a: &Vec3) -> Vec3 {
+        self.u * a.x + self.v * a.y + self.w * a.z
+    }
 }
 
 
@@ -146,3 +276,90 @@ pub fn linear_to_gamma(value: f32) -> f32 {
     0.0
 }
 
+// CIE XYZ to linear sRGB (D65 white point), negative components are clamped away
+pub fn xyz_to_linear_srgb(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    )
+}
+
+// Multiple importance sampling weight for a sample drawn with pdf_a, when pdf_b could also have produced it
+pub fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let a = pdf_a * pdf_a;
+    let b = pdf_b * pdf_b;
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn medium(id: u32, priority: u32, refractive_index: f32) -> Medium {
+        Medium { id, priority, refractive_index, absorption: Vec3::new(0.0, 0.0, 0.0) }
+    }
+
+    // Liquid in a glass: the glass wins where they overlap, and the liquid is back once the path leaves the glass
+    #[test]
+    fn medium_stack_picks_highest_priority() {
+        let (glass, water) = (medium(1, 2, 1.5), medium(2, 1, 1.33));
+        let mut media = MediumStack::default();
+        assert!(media.current().is_none());
+
+        media.push(glass);
+        media.push(water);
+        assert!(media.current().unwrap().same(&glass));
+        assert!(media.contains(&water));
+
+        media.remove(&glass);
+        assert!(media.current().unwrap().same(&water));
+        assert!(!media.contains(&glass));
+
+        media.remove(&water);
+        assert!(media.current().is_none());
+    }
+
+    #[test]
+    fn medium_stack_ties_go_to_latest() {
+        let (a, b) = (medium(1, 0, 1.5), medium(2, 0, 1.5));
+        let mut media = MediumStack::default();
+        media.push(a);
+        media.push(b);
+        assert_eq!(media.current().unwrap().id, 2);
+
+        // Objects sharing an index are still different media
+        media.remove(&b);
+        assert_eq!(media.current().unwrap().id, 1);
+        assert!(!media.contains(&b));
+    }
+
+    #[test]
+    fn medium_stack_forgets_outermost_when_full() {
+        let mut media = MediumStack::default();
+        for id in 1..=5 {
+            media.push(medium(id, 0, 1.5));
+        }
+        assert!(!media.contains(&medium(1, 0, 1.5)));
+        assert!((2..=5).all(|id| media.contains(&medium(id, 0, 1.5))));
+
+        // Removing a medium that isn't there leaves the stack alone
+        media.remove(&medium(1, 0, 1.5));
+        assert_eq!(media.current().unwrap().id, 5);
+    }
+
+    #[test]
+    fn ray_medium_index() {
+        let mut ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(ray.medium_index(), 1.0);
+
+        let mut media = MediumStack::default();
+        media.push(medium(1, 0, 1.33));
+        ray.media = Some(media);
+        assert_eq!(ray.medium_index(), 1.33);
+    }
+}
@@ -0,0 +1,382 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::hittable::{HitRecord, Hittable, Interval};
+use crate::material::Phase;
+use crate::random::RandomGenerator;
+use crate::spectrum::Spectrum;
+use crate::util::{Onb, Ray, Vec3};
+
+use Vec3 as Point;
+use Vec3 as Color;
+
+
+// ----------------------------------------------
+// Density grid ---------------------------------
+// ----------------------------------------------
+
+// Dense voxel grid of a cloud, smoke or fire, with optional temperature and emission channels.
+//
+// Raw grid files are little-endian: the magic "GRID", then u32 width, height, depth and channel count
+// (1 to 3, for density, temperature and emission), followed by the f32 voxels of each channel in turn,
+// with x varying fastest and z slowest.
+#[derive(Debug)]
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    density: Vec<f32>,
+    temperature: Option<Vec<f32>>,
+    emission: Option<Vec<f32>>,
+    max_density: f32,
+    max_temperature: f32,
+}
+
+impl DensityGrid {
+    pub fn load_raw(path: &str) -> io::Result<Arc<Self>> {
+        let bytes = fs::read(path)?;
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_owned());
+
+        if bytes.len() < 20 || &bytes[0..4] != b"GRID" {
+            return Err(invalid("not a raw density grid"));
+        }
+
+        let word = |i: usize| u32::from_le_bytes([bytes[4 * i], bytes[4 * i + 1], bytes[4 * i + 2], bytes[4 * i + 3]]) as usize;
+        let resolution = [word(1), word(2), word(3)];
+        let channels = word(4);
+        // A corrupt header could ask for more voxels than fit in memory, or even in a usize
+        let voxels = resolution[0].checked_mul(resolution[1]).and_then(|n| n.checked_mul(resolution[2]));
+        let size = voxels.and_then(|n| n.checked_mul(channels)?.checked_mul(4)?.checked_add(20));
+        let (Some(voxels), Some(size)) = (voxels, size) else {
+            return Err(invalid("density grid is too large"));
+        };
+        if voxels == 0 || !(1..=3).contains(&channels) || bytes.len() != size {
+            return Err(invalid("density grid size does not match its header"));
+        }
+
+        let mut values = bytes[20..].chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        let mut channel = || values.by_ref().take(voxels).collect::<Vec<f32>>();
+        let density = channel();
+        let temperature = (channels > 1).then(&mut channel);
+        let emission = (channels > 2).then(&mut channel);
+
+        Ok(Arc::new(DensityGrid::new(resolution, density, temperature, emission)))
+    }
+
+    // A directory of images, one slice per z layer in file name order, with density from their brightness
+    pub fn load_slices(dir: &str) -> Result<Arc<Self>, image::ImageError> {
+        let mut paths: Vec<_> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+
+        let mut resolution = [0, 0, paths.len()];
+        let mut density = Vec::new();
+        for path in paths.iter() {
+            let slice = image::open(Path::new(path))?.to_luma32f();
+            let (width, height) = (slice.width() as usize, slice.height() as usize);
+            if density.is_empty() {
+                resolution[0] = width;
+                resolution[1] = height;
+            } else if [width, height] != [resolution[0], resolution[1]] {
+                let reason = format!("slice {} does not match the size of the first one", path.display());
+                return Err(image::ImageError::IoError(io::Error::new(io::ErrorKind::InvalidData, reason)));
+            }
+
+            // Images store their top row first, while the grid starts at the bottom
+            for y in (0..height).rev() {
+                for x in 0..width {
+                    density.push(slice.get_pixel(x as u32, y as u32)[0]);
+                }
+            }
+        }
+
+        if density.is_empty() {
+            return Err(image::ImageError::IoError(io::Error::new(io::ErrorKind::NotFound, "no slices in directory")));
+        }
+        Ok(Arc::new(DensityGrid::new(resolution, density, None, None)))
+    }
+
+    // Fills the grid from a function over the unit cube returning density, temperature and emission
+    pub fn from_fn(resolution: [usize; 3], f: impl Fn(&Point) -> [f32; 3]) -> Arc<Self> {
+        let mut channels = [Vec::new(), Vec::new(), Vec::new()];
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    let p = Point::new(
+                        (x as f32 + 0.5) / resolution[0] as f32,
+                        (y as f32 + 0.5) / resolution[1] as f32,
+                        (z as f32 + 0.5) / resolution[2] as f32,
+                    );
+                    for (channel, value) in channels.iter_mut().zip(f(&p)) {
+                        channel.push(value);
+                    }
+                }
+            }
+        }
+
+        let [density, temperature, emission] = channels;
+        let used = |channel: Vec<f32>| channel.iter().any(|&v| v > 0.0).then_some(channel);
+        Arc::new(DensityGrid::new(resolution, density, used(temperature), used(emission)))
+    }
+
+    fn new(resolution: [usize; 3], density: Vec<f32>, temperature: Option<Vec<f32>>, emission: Option<Vec<f32>>) -> Self {
+        let max = |values: &[f32]| values.iter().fold(0.0_f32, |m, &v| m.max(v));
+        let max_density = max(&density);
+        let max_temperature = temperature.as_deref().map_or(0.0, max);
+        DensityGrid { resolution, density, temperature, emission, max_density, max_temperature }
+    }
+
+    // Trilinear interpolation between voxel centres, for a point in the unit cube
+    fn lookup(&self, values: &[f32], p: &Point) -> f32 {
+        let [nx, ny, nz] = self.resolution;
+        let coord = |v: f32, n: usize| {
+            let x = (v * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            let i = (x as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), x - i as f32)
+        };
+
+        let (x0, x1, fx) = coord(p.x, nx);
+        let (y0, y1, fy) = coord(p.y, ny);
+        let (z0, z1, fz) = coord(p.z, nz);
+        let at = |x: usize, y: usize, z: usize| values[(z * ny + y) * nx + x];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let plane = |z: usize| {
+            let bottom = lerp(at(x0, y0, z), at(x1, y0, z), fx);
+            let top = lerp(at(x0, y1, z), at(x1, y1, z), fx);
+            lerp(bottom, top, fy)
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+}
+
+
+// ----------------------------------------------
+// Heterogeneous medium -------------------------
+// ----------------------------------------------
+
+// A density grid stretched over an axis-aligned box. Collisions are found with delta tracking against the
+// grid's maximum density, and shadow rays estimate their transmittance with ratio tracking.
+pub struct GridMedium {
+    grid: Arc<DensityGrid>,
+    min: Point,
+    size: Vec3,
+    density: f32, // Extinction per unit of distance for a voxel density of 1
+    phase: Box<Phase>,
+    emission: Color, // Glow for an emission channel value of 1
+    temperature_scale: f32, // Kelvin for a temperature channel value of 1
+    blackbody: Vec<Color>, // Glow over temperature, up to the hottest voxel
+}
+
+// Light given off by a grid medium from its emission and temperature channels. The default doesn't glow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Glow {
+    pub emission: Color, // Glow for an emission channel value of 1
+    pub kelvin_per_unit: f32, // Temperature for a temperature channel value of 1
+    pub blackbody_intensity: f32, // Glow of the hottest voxel
+}
+
+impl GridMedium {
+    pub fn new(grid: Arc<DensityGrid>, min: Point, max: Point, density: f32, phase: Box<Phase>) -> Arc<Self> {
+        GridMedium::glowing(grid, min, max, density, phase, Glow::default())
+    }
+
+    // Fire: voxels glow with the black body colour of their temperature, and the hottest one with the
+    // blackbody intensity. Brightness falls with the fourth power of temperature, as it does for real
+    // black bodies. The emission channel adds a glow of its own on top.
+    pub fn glowing(grid: Arc<DensityGrid>, min: Point, max: Point, density: f32, phase: Box<Phase>, glow: Glow) -> Arc<Self> {
+        let blackbody = GridMedium::blackbody(&grid, glow.kelvin_per_unit, glow.blackbody_intensity);
+        Arc::new(GridMedium {
+            grid,
+            min,
+            size: max - min,
+            density: density.max(0.0),
+            phase,
+            emission: glow.emission,
+            temperature_scale: glow.kelvin_per_unit,
+            blackbody,
+        })
+    }
+
+    fn blackbody(grid: &DensityGrid, kelvin_per_unit: f32, intensity: f32) -> Vec<Color> {
+        // A grid that is cold everywhere doesn't glow, and leaving the table empty keeps `glow` from dividing by zero
+        let hottest = grid.max_temperature * kelvin_per_unit;
+        if hottest <= 0.0 {
+            return Vec::new();
+        }
+
+        (0..=64)
+            .map(|i| {
+                let kelvin = hottest * i as f32 / 64.0;
+                if kelvin < 500.0 {
+                    return Color::new(0.0, 0.0, 0.0); // Too cold to glow visibly
+                }
+                Spectrum::blackbody(kelvin).color() * (intensity * (kelvin / hottest).powi(4))
+            })
+            .collect()
+    }
+
+    // Entry and exit of the ray through the box, within the interval
+    fn overlap(&self, r: &Ray, interval: &Interval) -> Option<(f32, f32)> {
+        let (mut t_min, mut t_max) = (interval.min, interval.max);
+        let bounds = [
+            (self.min.x, self.size.x, r.origin().x, r.direction().x),
+            (self.min.y, self.size.y, r.origin().y, r.direction().y),
+            (self.min.z, self.size.z, r.origin().z, r.direction().z),
+        ];
+
+        for (min, size, origin, direction) in bounds {
+            let inverse = 1.0 / direction;
+            let t0 = (min - origin) * inverse;
+            let t1 = (min + size - origin) * inverse;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+
+        (t_min < t_max).then_some((t_min, t_max))
+    }
+
+    fn local(&self, p: &Point) -> Point {
+        (*p - self.min) / self.size
+    }
+
+    fn glow(&self, p: &Point) -> Color {
+        let mut glow = Color::new(0.0, 0.0, 0.0);
+        if let Some(emission) = &self.grid.emission {
+            glow = glow + self.emission * self.grid.lookup(emission, p);
+        }
+        if let Some(temperature) = &self.grid.temperature && !self.blackbody.is_empty() {
+            let hottest = self.grid.max_temperature * self.temperature_scale;
+            let x = (self.grid.lookup(temperature, p) * self.temperature_scale / hottest * 64.0).clamp(0.0, 64.0);
+            let i = (x as usize).min(63);
+            let t = x - i as f32;
+            glow = glow + self.blackbody[i] * (1.0 - t) + self.blackbody[i + 1] * t;
+        }
+        glow
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, r: &Ray, interval: Interval, rec: &mut HitRecord, rand: &mut RandomGenerator) -> bool {
+        let Some((t_min, t_max)) = self.overlap(r, &interval) else { return false };
+
+        let majorant = self.grid.max_density * self.density * r.direction().length();
+        if majorant <= 0.0 {
+            return false;
+        }
+
+        // Delta tracking: step through tentative collisions with the majorant, accepting each in
+        // proportion to the density there
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - rand.random_float()).ln() / majorant;
+            if t >= t_max {
+                return false;
+            }
+
+            let p = self.local(&r.at(t));
+            if self.grid.lookup(&self.grid.density, &p) >= rand.random_float() * self.grid.max_density {
+                rec.t = t;
+                rec.point = r.at(t);
+                rec.material = self.phase.emitting(self.glow(&p));
+
+                // Particles have no surface, so any frame does
+                let normal = r.direction().unit_vector() * -1.0;
+                rec.set_face_normal(r, &normal);
+                let frame = Onb::new(&normal);
+                rec.set_surface_coords(0.0, 0.0, frame.u, frame.v);
+                return true;
+            }
+        }
+    }
+
+    // Ratio tracking: the same tentative collisions, each letting through the part of the majorant
+    // that isn't real density
+    fn transmittance(&self, r: &Ray, interval: Interval, rand: &mut RandomGenerator) -> f32 {
+        let Some((t_min, t_max)) = self.overlap(r, &interval) else { return 1.0 };
+
+        let majorant = self.grid.max_density * self.density * r.direction().length();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+
+        let mut transmittance = 1.0;
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - rand.random_float()).ln() / majorant;
+            if t >= t_max {
+                return transmittance;
+            }
+
+            let density = self.grid.lookup(&self.grid.density, &self.local(&r.at(t)));
+            transmittance *= 1.0 - density / self.grid.max_density;
+
+            // Russian roulette, so thick media don't keep stepping for almost nothing
+            if transmittance < 0.1 {
+                if rand.random_float() < 0.5 {
+                    return 0.0;
+                }
+                transmittance *= 2.0;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes a raw grid file with the given header words and voxel values, and loads it back
+    fn load(name: &str, header: [u32; 4], voxels: &[f32]) -> io::Result<Arc<DensityGrid>> {
+        let mut bytes = b"GRID".to_vec();
+        bytes.extend(header.iter().flat_map(|word| word.to_le_bytes()));
+        bytes.extend(voxels.iter().flat_map(|value| value.to_le_bytes()));
+
+        let path = std::env::temp_dir().join(format!("rust-rt-{}-{name}.grid", std::process::id()));
+        fs::write(&path, bytes)?;
+        let grid = DensityGrid::load_raw(path.to_str().unwrap());
+        fs::remove_file(&path)?;
+        grid
+    }
+
+    fn error(result: io::Result<Arc<DensityGrid>>) -> String {
+        let error = result.expect_err("grid should be rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        error.to_string()
+    }
+
+    #[test]
+    fn load_raw_reads_channels_in_turn() {
+        let grid = load("channels", [2, 1, 1, 2], &[0.5, 1.0, 300.0, 600.0]).unwrap();
+        assert_eq!(grid.resolution, [2, 1, 1]);
+        assert_eq!(grid.density, [0.5, 1.0]);
+        assert_eq!(grid.temperature.as_deref(), Some(&[300.0, 600.0][..]));
+        assert!(grid.emission.is_none());
+        assert_eq!(grid.max_density, 1.0);
+    }
+
+    #[test]
+    fn load_raw_rejects_bad_headers() {
+        let path = std::env::temp_dir().join(format!("rust-rt-{}-magic.grid", std::process::id()));
+        fs::write(&path, b"VOXL\x01\0\0\0\x01\0\0\0\x01\0\0\0\x01\0\0\0\0\0\0\0").unwrap();
+        let result = DensityGrid::load_raw(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error(result), "not a raw density grid");
+
+        let huge = error(load("huge", [u32::MAX, u32::MAX, u32::MAX, 3], &[1.0]));
+        assert_eq!(huge, "density grid is too large");
+        assert_eq!(error(load("channels", [1, 1, 1, 4], &[1.0; 4])), "density grid size does not match its header");
+        assert_eq!(error(load("empty", [0, 4, 4, 1], &[])), "density grid size does not match its header");
+    }
+
+    #[test]
+    fn load_raw_rejects_size_mismatches() {
+        let message = "density grid size does not match its header";
+        assert_eq!(error(load("short", [2, 2, 2, 1], &[1.0; 7])), message);
+        assert_eq!(error(load("long", [2, 2, 2, 1], &[1.0; 9])), message);
+        assert_eq!(error(load("missing", [2, 2, 2, 2], &[1.0; 8])), message);
+    }
+}